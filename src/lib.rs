@@ -3,3 +3,6 @@ pub mod section_2;
 pub mod section_3;
 pub mod section_4;
 pub mod section_5;
+
+#[cfg(test)]
+mod test_util;
@@ -17,10 +17,18 @@ use rust_atomics::section_3::{
 
 #[allow(unused)]
 use rust_atomics::section_4::{
-    channel_avoid_borrowing_main, channel_blocking_main, channel_one_off_main,
-    channel_send_receive, spin_lock_main,
+    block_on_main, cancel_main, channel_avoid_borrowing_main, channel_blocking_main,
+    channel_broadcast_main, channel_deadline_main, channel_linked_blocks_main,
+    channel_mpsc_linked_main, channel_one_off_main, channel_priority_main, channel_rendezvous_main,
+    channel_ring_buffer_main, channel_send_receive, channel_watch_main, disruptor_main,
+    durable_queue_main, one_shot_main, rpc_main, select_main, spin_lock_main,
+    spsc_ring_buffer_main,
 };
 
+#[cfg(target_os = "linux")]
+#[allow(unused)]
+use rust_atomics::section_4::shared_memory_spsc_main;
+
 fn main() {
     // ------section 1------
     // thread_main();
@@ -52,4 +60,21 @@ fn main() {
     // channel_send_receive();
     // channel_avoid_borrowing_main();
     // channel_blocking_main();
+    // channel_ring_buffer_main();
+    // channel_mpsc_linked_main();
+    // spsc_ring_buffer_main();
+    // select_main();
+    // channel_broadcast_main();
+    // channel_watch_main();
+    // channel_rendezvous_main();
+    // block_on_main();
+    // channel_priority_main();
+    // rpc_main();
+    // channel_deadline_main();
+    // durable_queue_main();
+    // shared_memory_spsc_main();
+    // disruptor_main();
+    // channel_linked_blocks_main();
+    // cancel_main();
+    // one_shot_main();
 }
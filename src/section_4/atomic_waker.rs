@@ -0,0 +1,99 @@
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 1;
+const WAKING: usize = 2;
+
+/**
+ * A slot for a single Waker, the async version of storing the receiving Thread so the sender can unpark() it (section_4/channel_blocking.rs).
+ *
+ * A Future's poll() registers the Waker from its Context, and the other side calls wake() once the value is ready. Registering and waking can happen at the same time on two threads, so instead of a lock the slot has a small state machine:
+ * - WAITING, nobody is touching the Waker
+ * - REGISTERING, poll() is replacing the Waker
+ * - WAKING, wake() is taking the Waker out (can be combined with REGISTERING)
+ *
+ * If wake() finds a register() in progress, it only sets the WAKING bit and leaves. register() sees the bit when it tries to go back to WAITING, and does the wake itself, so the wake up is never lost.
+ *
+ * Only one thread may call register() at a time, which holds as there is a single Receiver.
+ */
+pub struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Sync for AtomicWaker {}
+unsafe impl Send for AtomicWaker {}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    ///
+    /// Stores the Waker to be woken by the next wake()
+    ///
+    pub fn register(&self, waker: &Waker) {
+        match self.state.compare_exchange(
+            WAITING,
+            REGISTERING,
+            Ordering::Acquire,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                // Safety: we are in the REGISTERING state, so wake() will not touch the slot
+                let slot = unsafe { &mut *self.waker.get() };
+                if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                    *slot = Some(waker.clone());
+                }
+
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // A wake() came in whilst we were registering, it left the waking to us
+                    let waker = slot.take();
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            // wake() is running right now, so it may have missed our new Waker
+            Err(WAKING) => waker.wake_by_ref(),
+            Err(_) => {}
+        }
+    }
+
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                // Safety: we set WAKING from WAITING, so register() will not touch the slot
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            // register() is in progress and will see WAKING, or another wake() is already running
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use super::channel_sender_receiver;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/**
+ * The smallest executor we can get away with, enough to .await the channels without pulling in tokio.
+ *
+ * The future is polled on the current thread. When it returns Pending, the thread parks till the Waker is woken, and waking is just unpark() on this thread, the same as section_4/channel_blocking.rs does by hand. A spurious wake up from park() only costs an extra poll.
+ */
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+pub fn block_on_main() {
+    let (sender, receiver) = channel_sender_receiver::channel::<&str>();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            sender.send("hello world!");
+        });
+
+        let chan_msg = block_on(async {
+            println!("waiting...");
+            receiver.await
+        });
+        println!("chan_msg {:?}", chan_msg);
+        assert_eq!(chan_msg, "hello world!");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::block_on;
+    use crate::section_4::channel_sender_receiver;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn returns_the_output_of_a_ready_future() {
+        assert_eq!(block_on(async { 1 + 2 }), 3);
+    }
+
+    #[test]
+    fn awaits_a_message_sent_from_another_thread() {
+        let (sender, receiver) = channel_sender_receiver::channel();
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send(String::from("hello"));
+        });
+
+        assert_eq!(block_on(receiver), "hello");
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn awaits_a_message_sent_before_polling() {
+        let (sender, receiver) = channel_sender_receiver::channel();
+        sender.send(42);
+
+        let doubled = block_on(async { receiver.await * 2 });
+        assert_eq!(doubled, 84);
+    }
+}
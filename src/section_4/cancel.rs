@@ -0,0 +1,208 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::channel_vec_dequeue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+// A callback registered by scope(), with its lifetime erased. scope() removes it before the borrow it points to ends.
+struct Callback {
+    id: u64,
+    on_cancel: *const (dyn Fn() + Sync),
+}
+
+// Safety: the callbacks are Sync, and only called whilst their scope() is still running
+unsafe impl Send for Callback {}
+
+struct Inner {
+    cancelled: AtomicBool,
+    next_id: AtomicU64,
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+/**
+ * A thread blocked in receive() is asleep, in thread::park() (section_4/channel_blocking.rs) or Condvar::wait() (section_4/channel_vec_dequeue.rs), and setting a flag does nothing till something wakes it to look at the flag. During shutdown nothing will, so the thread never stops.
+ *
+ * A CancelToken is that flag plus the wake up. Clones share the same token, and cancel() on any of them:
+ * - stores cancelled with SeqCst
+ * - calls every callback registered with scope(), which wakes the thread the way it is waiting (unpark() the parked thread, or take the queue lock and notify_all() the Condvar)
+ *
+ * The receive_cancellable() methods register their callback, then check cancelled each time round their wait loop and return Err(Cancelled). Registering takes the callbacks lock, and cancel() stores cancelled before taking it, so a receiver either registers in time to be woken or sees cancelled on its first check.
+ *
+ * scope() lets a callback borrow from the stack (the Channel, the current Thread) rather than needing 'static. It is only registered for the duration of the call, and cancel() calls the callbacks whilst holding the lock that scope() needs to remove its callback, so a callback is never called after its scope() has returned. The callbacks must not call into the token themselves.
+ */
+#[derive(Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+// Removes the callback when scope() returns, or unwinds
+struct Registration<'a> {
+    token: &'a CancelToken,
+    id: u64,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                next_id: AtomicU64::new(0),
+                callbacks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    ///
+    /// Cancels every clone of this token, waking anything waiting in scope()
+    ///
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for callback in self.inner.callbacks.lock().unwrap().iter() {
+            // Safety: the callback's scope() is blocked on this lock if it is trying to return, so the borrow is still alive
+            unsafe { (*callback.on_cancel)() };
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// Runs body with on_cancel registered, so a cancel() from another thread calls it. Check is_cancelled() in body after anything that could miss the call.
+    ///
+    pub fn scope<R>(&self, on_cancel: &(dyn Fn() + Sync), body: impl FnOnce() -> R) -> R {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        // Safety: only the lifetime changes, and the Registration removes the callback before on_cancel's borrow ends
+        let on_cancel: *const (dyn Fn() + Sync + 'static) = unsafe { mem::transmute(on_cancel) };
+        self.inner
+            .callbacks
+            .lock()
+            .unwrap()
+            .push(Callback { id, on_cancel });
+
+        let _registration = Registration { token: self, id };
+        body()
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        let mut callbacks = match self.token.inner.callbacks.lock() {
+            Ok(callbacks) => callbacks,
+            // A callback panicked inside cancel(), the list is still whole
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        callbacks.retain(|callback| callback.id != self.id);
+    }
+}
+
+pub fn cancel_main() {
+    let (sender, receiver) = channel_vec_dequeue::channel::<String>();
+    let shutdown = CancelToken::new();
+
+    thread::scope(|s| {
+        let worker = s.spawn(|| {
+            let mut handled = 0;
+            while let Ok(Some(job)) = receiver.receive_cancellable(&shutdown) {
+                println!("handling {job}");
+                handled += 1;
+            }
+            handled
+        });
+
+        sender.send("job 1".to_string());
+        sender.send("job 2".to_string());
+        thread::sleep(Duration::from_millis(50));
+
+        // The sender is still alive, so without the token the worker would wait forever
+        shutdown.cancel();
+        println!("worker stopped after {} jobs", worker.join().unwrap());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelToken, Cancelled};
+    use crate::section_4::{channel_blocking, channel_vec_dequeue};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn cancel_wakes_a_parked_receiver() {
+        let mut channel = channel_blocking::Channel::<i32>::new();
+        let token = CancelToken::new();
+
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            let canceller = token.clone();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                canceller.cancel();
+            });
+
+            assert_eq!(receiver.receive_cancellable(&token), Err(Cancelled));
+            // Cancelled for good
+            assert_eq!(receiver.receive_cancellable(&token), Err(Cancelled));
+            drop(sender);
+        });
+    }
+
+    #[test]
+    fn cancel_wakes_receivers_waiting_on_a_condvar() {
+        let channel = channel_vec_dequeue::Channel::<i32>::new();
+        let (sender, receiver) = channel_vec_dequeue::channel::<i32>();
+        let token = CancelToken::new();
+
+        thread::scope(|s| {
+            s.spawn(|| assert_eq!(channel.receive_cancellable(&token), Err(Cancelled)));
+            s.spawn(|| {
+                assert_eq!(receiver.receive_cancellable(&token), Ok(Some(1)));
+                assert_eq!(receiver.receive_cancellable(&token), Err(Cancelled));
+            });
+
+            thread::sleep(Duration::from_millis(20));
+            sender.send(1);
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        drop(sender);
+    }
+
+    #[test]
+    fn callbacks_only_run_whilst_their_scope_is_running() {
+        let token = CancelToken::new();
+        let calls = AtomicUsize::new(0);
+        let count = || {
+            calls.fetch_add(1, Ordering::Relaxed);
+        };
+
+        assert_eq!(token.scope(&count, || 5), 5);
+        token.cancel();
+        assert_eq!(calls.into_inner(), 0);
+
+        // Registering after cancel() does not call the callback, the body sees is_cancelled() instead
+        let other = CancelToken::new();
+        other.cancel();
+        assert!(other.scope(&|| panic!("called after cancel"), || other.is_cancelled()));
+    }
+}
@@ -6,6 +6,8 @@ use std::{
     thread::{self, Thread},
 };
 
+use super::{CancelToken, Cancelled};
+
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
@@ -76,6 +78,23 @@ impl<T> Receiver<'_, T> {
 
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
+
+    ///
+    /// Same as receive(), but returns Err(Cancelled) once the token is cancelled from another thread
+    ///
+    #[allow(unused)]
+    pub fn receive_cancellable(&self, token: &CancelToken) -> Result<T, Cancelled> {
+        let thread = thread::current();
+        token.scope(&move || thread.unpark(), || loop {
+            if self.channel.ready.swap(false, Ordering::Acquire) {
+                return Ok(unsafe { (*self.channel.message.get()).assume_init_read() });
+            }
+            if token.is_cancelled() {
+                return Err(Cancelled);
+            }
+            thread::park();
+        })
+    }
 }
 
 impl<T> Drop for Channel<T> {
@@ -90,6 +109,7 @@ impl<T> Drop for Channel<T> {
  *
  * From section_4/channel_avoid_blocking.rs, we used pointers to remove Arc constraint. Now we have gone a set further and kept the reference of the current thread (the caller thread that calls split()) in sender to unpark() the thread and let receive to return back the value, if its ready else put the thread to sleep.
  *
+ * receive_cancellable() also registers an unpark() of the receiving thread with a CancelToken (section_4/cancel.rs), so another thread can wake it and make it give up.
  *
  */
 
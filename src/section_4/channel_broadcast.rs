@@ -0,0 +1,362 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// The subscriber fell behind and this many messages were overwritten before it could read them. The subscriber has been moved on to the oldest message still in the ring.
+    Lagged(u64),
+    /// The publisher has been dropped and every message has been read.
+    Closed,
+}
+
+struct Ring<T> {
+    slots: Vec<Option<T>>,
+    // Sequence number the next published message will get
+    tail: u64,
+    closed: bool,
+}
+
+// Private impl, pub fn exists to return tuple pair of Publisher, Subscriber
+struct Channel<T> {
+    ring: Mutex<Ring<T>>,
+    // Length of ring.slots, fixed at creation
+    capacity: usize,
+    message_ready: Condvar,
+    subscribers: AtomicUsize,
+    // Copies of ring.tail and ring.closed, written whilst holding the lock so they can be read without it
+    published: AtomicU64,
+    publisher_dropped: AtomicBool,
+}
+
+/**
+ * All the other channels in section_4 hand each message to exactly one receiver. A broadcast channel is the pub/sub model from section_4/channel_vec_dequeue.rs, every subscriber sees every message.
+ *
+ * The messages live in a shared ring of a fixed capacity, and each message is numbered with an ever increasing sequence number. Each Subscriber keeps its own cursor (the sequence number it will read next) so subscribers move through the ring at their own pace, and receive() clones the message out rather than taking it.
+ *
+ * The publisher never waits for the subscribers. Once the ring is full, the next send overwrites the oldest message. A subscriber whose cursor points at an overwritten message gets Err(Lagged(n)) with the number of messages it missed, and its cursor is moved up to the oldest message still in the ring, so the next receive() carries on from there.
+ *
+ * The ring is guarded by a Mutex, with a Condvar to wake subscribers that are waiting for the next message, same as section_1/thread_condvar.rs.
+ *
+ * New subscribers (from Publisher::subscribe) start at the tail, so they only see messages sent after they subscribed.
+ *
+ * len() is per end, a Subscriber reports how many messages it has not read yet (at most the capacity, anything older has been overwritten), the Publisher reports how many messages the ring holds. They are read from atomics, so they never wait on the ring lock.
+ */
+pub struct Publisher<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Subscriber<T> {
+    channel: Arc<Channel<T>>,
+    next: u64,
+}
+
+pub fn channel<T: Clone>(capacity: usize) -> (Publisher<T>, Subscriber<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+
+    let arc = Arc::new(Channel {
+        ring: Mutex::new(Ring {
+            slots: (0..capacity).map(|_| None).collect(),
+            tail: 0,
+            closed: false,
+        }),
+        capacity,
+        message_ready: Condvar::new(),
+        subscribers: AtomicUsize::new(1),
+        published: AtomicU64::new(0),
+        publisher_dropped: AtomicBool::new(false),
+    });
+    (
+        Publisher {
+            channel: arc.clone(),
+        },
+        Subscriber {
+            channel: arc,
+            next: 0,
+        },
+    )
+}
+
+impl<T: Clone> Publisher<T> {
+    ///
+    /// Places the message in the ring (overwriting the oldest once full), and returns the number of subscribers at the time of sending
+    ///
+    pub fn send(&self, message: T) -> usize {
+        let mut ring = self.channel.ring.lock().unwrap();
+        let index = (ring.tail % ring.slots.len() as u64) as usize;
+        ring.slots[index] = Some(message);
+        ring.tail += 1;
+        self.channel.published.store(ring.tail, Ordering::Relaxed);
+        drop(ring);
+
+        self.channel.message_ready.notify_all();
+        self.channel.subscribers.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let tail = self.channel.ring.lock().unwrap().tail;
+        self.channel.subscribers.fetch_add(1, Ordering::Relaxed);
+        Subscriber {
+            channel: self.channel.clone(),
+            next: tail,
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.channel.subscribers.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// Number of messages held in the ring
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel
+            .published
+            .load(Ordering::Relaxed)
+            .min(self.channel.capacity as u64) as usize
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.channel.capacity)
+    }
+
+    ///
+    /// Always 1, there is a single Publisher
+    ///
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        1
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.subscriber_count()
+    }
+
+    ///
+    /// True once every Subscriber has been dropped, send() still works but nobody will see the messages
+    ///
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.subscriber_count() == 0
+    }
+}
+
+impl<T> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        let mut ring = self.channel.ring.lock().unwrap();
+        ring.closed = true;
+        self.channel
+            .publisher_dropped
+            .store(true, Ordering::Relaxed);
+        drop(ring);
+        self.channel.message_ready.notify_all();
+    }
+}
+
+impl<T: Clone> Subscriber<T> {
+    ///
+    /// Ok(None) if there is no new message yet
+    ///
+    pub fn try_receive(&mut self) -> Result<Option<T>, RecvError> {
+        let ring = self.channel.ring.lock().unwrap();
+        read(&mut self.next, &ring)
+    }
+
+    ///
+    /// Blocks till the next message is published, or the publisher is dropped
+    ///
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        let mut ring = self.channel.ring.lock().unwrap();
+        loop {
+            if let Some(message) = read(&mut self.next, &ring)? {
+                return Ok(message);
+            }
+            ring = self.channel.message_ready.wait(ring).unwrap();
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        let ring = self.channel.ring.lock().unwrap();
+        self.next < ring.tail || ring.closed
+    }
+
+    ///
+    /// Number of messages this subscriber has not read yet, capped at the capacity as anything older has been overwritten
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        let published = self.channel.published.load(Ordering::Relaxed);
+        published
+            .saturating_sub(self.next)
+            .min(self.channel.capacity as u64) as usize
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.channel.capacity)
+    }
+
+    ///
+    /// 1 till the Publisher is dropped
+    ///
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        if self.channel.publisher_dropped.load(Ordering::Relaxed) {
+            0
+        } else {
+            1
+        }
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.subscribers.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// True once the Publisher has been dropped, messages still in the ring can be received
+    ///
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.sender_count() == 0
+    }
+}
+
+// Moves the subscriber's cursor on, reporting a lag if the messages it was up to have been overwritten
+fn read<T: Clone>(next: &mut u64, ring: &Ring<T>) -> Result<Option<T>, RecvError> {
+    let capacity = ring.slots.len() as u64;
+    let oldest = ring.tail.saturating_sub(capacity);
+
+    if *next < oldest {
+        let missed = oldest - *next;
+        *next = oldest;
+        return Err(RecvError::Lagged(missed));
+    }
+
+    if *next == ring.tail {
+        return if ring.closed {
+            Err(RecvError::Closed)
+        } else {
+            Ok(None)
+        };
+    }
+
+    let message = ring.slots[(*next % capacity) as usize].clone();
+    *next += 1;
+    Ok(message)
+}
+
+// The clone carries on from the same cursor as the original
+impl<T> Clone for Subscriber<T> {
+    fn clone(&self) -> Self {
+        self.channel.subscribers.fetch_add(1, Ordering::Relaxed);
+        Subscriber {
+            channel: self.channel.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.channel.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn channel_broadcast_main() {
+    let (publisher, first) = channel::<String>(4);
+    let second = publisher.subscribe();
+
+    thread::scope(|s| {
+        for (name, mut subscriber) in [("first", first), ("second", second)] {
+            s.spawn(move || loop {
+                match subscriber.receive() {
+                    Ok(message) => println!("{name} got {:?}", message),
+                    Err(RecvError::Lagged(n)) => println!("{name} missed {n} messages"),
+                    Err(RecvError::Closed) => break,
+                }
+            });
+        }
+
+        for i in 0..3 {
+            let count = publisher.send(format!("message {i}"));
+            println!("sent to {count} subscribers");
+        }
+        drop(publisher);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, RecvError};
+    use std::thread;
+
+    #[test]
+    fn every_subscriber_sees_every_message() {
+        let (publisher, mut first) = channel(8);
+        let mut second = publisher.subscribe();
+
+        assert_eq!(publisher.send(1), 2);
+        assert_eq!(publisher.send(2), 2);
+        assert_eq!(publisher.len(), 2);
+
+        assert_eq!(first.receive(), Ok(1));
+        assert_eq!(first.receive(), Ok(2));
+        assert_eq!(first.try_receive(), Ok(None));
+        assert!(first.is_empty());
+        assert_eq!(second.len(), 2);
+        assert_eq!(second.receive(), Ok(1));
+        assert_eq!(second.receive(), Ok(2));
+
+        assert!(!first.is_closed());
+        drop(publisher);
+        assert!(first.is_closed());
+        assert_eq!(first.receive(), Err(RecvError::Closed));
+        assert_eq!(second.receive(), Err(RecvError::Closed));
+    }
+
+    #[test]
+    fn slow_subscriber_is_told_how_many_it_missed() {
+        let (publisher, mut subscriber) = channel(2);
+        for i in 0..5 {
+            publisher.send(i);
+        }
+        assert_eq!(publisher.len(), 2);
+        assert_eq!(subscriber.len(), 2);
+
+        assert_eq!(subscriber.receive(), Err(RecvError::Lagged(3)));
+        assert_eq!(subscriber.receive(), Ok(3));
+        assert_eq!(subscriber.receive(), Ok(4));
+        assert_eq!(subscriber.try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn new_subscriber_starts_from_the_tail() {
+        let (publisher, _first) = channel(4);
+        publisher.send("before");
+
+        let mut late = publisher.subscribe();
+        assert_eq!(late.try_receive(), Ok(None));
+
+        let t = thread::spawn(move || late.receive());
+        publisher.send("after");
+        assert_eq!(t.join().unwrap(), Ok("after"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{channel_mpsc_linked, IntoIter, Iter, Receive, TryIter};
+
+struct Envelope<T> {
+    message: T,
+    expires_at: Instant,
+}
+
+type OnExpired<T> = Box<dyn Fn(T) + Send + Sync>;
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<T> {
+    queue: Mutex<VecDeque<Envelope<T>>>,
+    message_ready: Condvar,
+    senders: AtomicUsize,
+    receiver_dropped: AtomicBool,
+    // Copy of the queue length, written whilst holding the lock so len() can read it without locking
+    len: AtomicUsize,
+    // Messages skipped by receive() because they had expired
+    expired: AtomicUsize,
+    on_expired: Option<OnExpired<T>>,
+}
+
+/**
+ * During a spike, work piles up in a queue and by the time it is received it may be too late to be worth doing (the caller has given up, or a newer update has replaced it).
+ *
+ * This is section_4/channel_vec_dequeue.rs with an expiry Instant on every message. receive() skips (and counts) every message at the front of the queue whose expiry has passed, and only returns a message that is still live. Expired messages are checked when they reach the front, so a message never expires whilst it is being handed out.
+ *
+ * Skipped messages are dropped, unless the channel was made with a callback (channel_with_callback) or a dead letter queue (channel_with_dead_letters), which get the expired messages instead. The callback is run after the queue lock has been released, so it can be slow without holding up the senders.
+ *
+ * The Senders are counted, so receive() returns None once every Sender has been dropped and no live message is left.
+ */
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+///
+/// Expired messages are dropped
+///
+#[allow(unused)]
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(None)
+}
+
+///
+/// Expired messages are passed to on_expired, on the receiving thread
+///
+#[allow(unused)]
+pub fn channel_with_callback<T>(
+    on_expired: impl Fn(T) + Send + Sync + 'static,
+) -> (Sender<T>, Receiver<T>) {
+    new_channel(Some(Box::new(on_expired)))
+}
+
+///
+/// Expired messages are sent to the third Receiver, which ends once the channel has been dropped
+///
+#[allow(unused)]
+pub fn channel_with_dead_letters<T: Send + 'static>(
+) -> (Sender<T>, Receiver<T>, channel_mpsc_linked::Receiver<T>) {
+    let (dead_letters, dead_letter_receiver) = channel_mpsc_linked::channel();
+    let (sender, receiver) = channel_with_callback(move |message| dead_letters.send(message));
+    (sender, receiver, dead_letter_receiver)
+}
+
+fn new_channel<T>(on_expired: Option<OnExpired<T>>) -> (Sender<T>, Receiver<T>) {
+    let arc = Arc::new(Channel {
+        queue: Mutex::new(VecDeque::new()),
+        message_ready: Condvar::new(),
+        senders: AtomicUsize::new(1),
+        receiver_dropped: AtomicBool::new(false),
+        len: AtomicUsize::new(0),
+        expired: AtomicUsize::new(0),
+        on_expired,
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver { channel: arc },
+    )
+}
+
+impl<T> Channel<T> {
+    ///
+    /// Pops the first live message, moving any expired ones in front of it into expired
+    ///
+    fn pop_live(&self, queue: &mut VecDeque<Envelope<T>>, expired: &mut Vec<T>) -> Option<T> {
+        let now = Instant::now();
+        let mut live = None;
+        while let Some(envelope) = queue.pop_front() {
+            if envelope.expires_at > now {
+                live = Some(envelope.message);
+                break;
+            }
+            expired.push(envelope.message);
+        }
+        self.len.store(queue.len(), Ordering::Relaxed);
+        live
+    }
+
+    // Called without the queue lock held
+    fn dead_letters(&self, expired: Vec<T>) {
+        self.expired.fetch_add(expired.len(), Ordering::Relaxed);
+        if let Some(on_expired) = &self.on_expired {
+            expired.into_iter().for_each(on_expired);
+        }
+    }
+
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        if self.receiver_dropped.load(Ordering::Relaxed) {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+impl<T> Sender<T> {
+    ///
+    /// The message is skipped by receive() once expires_at has passed
+    ///
+    pub fn send(&self, message: T, expires_at: Instant) {
+        let mut queue = self.channel.queue.lock().unwrap();
+        queue.push_back(Envelope {
+            message,
+            expires_at,
+        });
+        self.channel.len.store(queue.len(), Ordering::Relaxed);
+        drop(queue);
+
+        self.channel.message_ready.notify_one();
+    }
+
+    ///
+    /// Same as send(), with the expiry given as time to live from now
+    ///
+    pub fn send_ttl(&self, message: T, ttl: Duration) {
+        self.send(message, Instant::now() + ttl);
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len.load(Ordering::Relaxed)
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///
+    /// None, the queue grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::Release) == 1 {
+            drop(self.channel.queue.lock().unwrap());
+            self.channel.message_ready.notify_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    ///
+    /// Skips expired messages, None if there is no live message right now
+    ///
+    pub fn try_receive(&self) -> Option<T> {
+        let mut expired = Vec::new();
+        let message = {
+            let mut queue = self.channel.queue.lock().unwrap();
+            self.channel.pop_live(&mut queue, &mut expired)
+        };
+        self.channel.dead_letters(expired);
+        message
+    }
+
+    ///
+    /// Skips expired messages and blocks till a live one is sent, returns None once every Sender has been dropped and no live message is left
+    ///
+    pub fn receive(&self) -> Option<T> {
+        let mut queue = self.channel.queue.lock().unwrap();
+        loop {
+            let mut expired = Vec::new();
+            let message = self.channel.pop_live(&mut queue, &mut expired);
+
+            if !expired.is_empty() {
+                drop(queue);
+                self.channel.dead_letters(expired);
+                if message.is_some() {
+                    return message;
+                }
+                queue = self.channel.queue.lock().unwrap();
+                continue;
+            }
+
+            if message.is_some() {
+                return message;
+            }
+
+            if self.channel.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+
+            queue = self.channel.message_ready.wait(queue).unwrap();
+        }
+    }
+
+    ///
+    /// True if receive() would not block, a live message is queued or every Sender has been dropped
+    ///
+    pub fn is_ready(&self) -> bool {
+        let now = Instant::now();
+        let queue = self.channel.queue.lock().unwrap();
+        queue.iter().any(|envelope| envelope.expires_at > now)
+            || self.channel.senders.load(Ordering::Relaxed) == 0
+    }
+
+    ///
+    /// Number of messages that have been skipped because they expired
+    ///
+    pub fn expired_count(&self) -> usize {
+        self.channel.expired.load(Ordering::Relaxed)
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    ///
+    /// Includes messages that have expired but not been skipped yet
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len.load(Ordering::Relaxed)
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.receiver_dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+pub fn channel_deadline_main() {
+    let (sender, receiver, dead_letters) = channel_with_dead_letters::<String>();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..10 {
+                sender.send_ttl(format!("job {i}"), Duration::from_millis(50));
+            }
+        });
+
+        // A slow consumer, most jobs expire whilst it works on the first ones
+        for job in &receiver {
+            println!("working on {job}");
+            thread::sleep(Duration::from_millis(20));
+        }
+        println!("{} jobs expired", receiver.expired_count());
+    });
+
+    // The dead letter queue ends once the channel has been dropped
+    drop(receiver);
+    for job in dead_letters {
+        println!("dead letter {job}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, channel_with_callback, channel_with_dead_letters};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn expired_messages_are_skipped_and_counted() {
+        let (sender, receiver) = channel();
+        let now = Instant::now();
+        sender.send("stale", now);
+        sender.send("fresh", now + Duration::from_secs(60));
+        sender.send("also stale", now);
+
+        assert_eq!(receiver.receive(), Some("fresh"));
+        assert_eq!(receiver.expired_count(), 1);
+        assert_eq!(receiver.try_receive(), None);
+        assert_eq!(receiver.expired_count(), 2);
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn expired_messages_go_to_the_callback_or_dead_letters() {
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let (sender, receiver) = {
+            let expired = expired.clone();
+            channel_with_callback(move |message| expired.lock().unwrap().push(message))
+        };
+        sender.send_ttl(1, Duration::ZERO);
+        sender.send_ttl(2, Duration::from_secs(60));
+        assert_eq!(receiver.receive(), Some(2));
+        assert_eq!(*expired.lock().unwrap(), [1]);
+
+        let (sender, receiver, dead_letters) = channel_with_dead_letters();
+        sender.send_ttl("late", Duration::ZERO);
+        drop(sender);
+        assert_eq!(receiver.receive(), None);
+        drop(receiver);
+        assert_eq!(dead_letters.into_iter().collect::<Vec<_>>(), ["late"]);
+    }
+
+    #[test]
+    fn receive_waits_past_expired_messages_for_a_live_one() {
+        let (sender, receiver) = channel();
+        sender.send_ttl(0, Duration::ZERO);
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send_ttl(1, Duration::from_secs(60));
+        });
+
+        assert!(!receiver.is_ready());
+        assert_eq!(receiver.receive(), Some(1));
+        assert_eq!(receiver.expired_count(), 1);
+        // The only sender is dropped at the end of the thread
+        assert_eq!(receiver.receive(), None);
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::{
+        atomic::{self, AtomicPtr, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Thread},
+};
+
+use super::{CachePadded, IntoIter, Iter, Receive, TryIter};
+
+// Slots per block. Positions count one extra per block (LAP), the last one marks the switch to the next block.
+const BLOCK_CAP: usize = 31;
+const LAP: usize = BLOCK_CAP + 1;
+
+// Slot state bits
+const WRITE: usize = 1;
+const READ: usize = 2;
+const DESTROY: usize = 4;
+
+struct Slot<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+// A position in the queue and the block it is in
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<T> {
+    head: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T>>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // Receivers that are parked (or about to park), senders only lock waiters if this is above 0
+    sleeping: AtomicUsize,
+    waiters: Mutex<Vec<Thread>>,
+}
+
+unsafe impl<T> Send for Channel<T> where T: Send {}
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+/**
+ * section_4/channel_vec_dequeue.rs takes one Mutex for every send and receive, so under a burst every thread queues up on the same lock. This is an unbounded multiple producer multiple consumer channel that never takes a lock to send or receive (the list flavour of the crossbeam-channel crate).
+ *
+ * The queue is a linked list of blocks, each with BLOCK_CAP slots, and a head and tail position that count through them:
+ * - A sender claims a slot by moving tail on with compare_exchange, writes the message into it, then sets the slot's WRITE bit with Release
+ * - A receiver claims a slot by moving head on with compare_exchange, waits for the WRITE bit with Acquire (the sender may still be writing), then reads the message
+ *
+ * Every block has LAP positions, one more than it has slots. The sender that claims the last slot allocates the next block ahead of time, installs it and moves tail past the extra position. Anyone who sees tail (or head) sitting on the extra position knows the next block is being installed, and yields till it is.
+ *
+ * A block is freed once every slot in it has been read. Readers can finish out of order, so the reader of the last slot walks the block setting DESTROY on each slot. A slot whose READ bit is not set yet is still being read, so that reader sees DESTROY when it sets READ and carries on freeing the block from the next slot. Whoever reaches the end frees it.
+ *
+ * Receivers block the way section_4/channel_blocking.rs does, by parking the thread. As there can be many of them, parked receivers put their Thread in waiters, which is only locked if the sleeping counter says someone is asleep. The sleeping count and the SeqCst compare_exchange on tail pair up (section_3/seqcst_ordering.rs), so either the receiver sees the new message before parking or the sender sees that it needs to wake someone.
+ *
+ * Senders and Receivers can both be cloned. receive() returns None once every Sender has been dropped and the queue is drained.
+ */
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let block = Block::new();
+    let arc = Arc::new(Channel {
+        head: CachePadded(Position {
+            index: AtomicUsize::new(0),
+            block: AtomicPtr::new(block),
+        }),
+        tail: CachePadded(Position {
+            index: AtomicUsize::new(0),
+            block: AtomicPtr::new(block),
+        }),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        sleeping: AtomicUsize::new(0),
+        waiters: Mutex::new(Vec::new()),
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver { channel: arc },
+    )
+}
+
+impl<T> Block<T> {
+    fn new() -> *mut Block<T> {
+        Box::into_raw(Box::new(Block {
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots: std::array::from_fn(|_| Slot {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicUsize::new(0),
+            }),
+        }))
+    }
+
+    fn wait_next(&self) -> *mut Block<T> {
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            thread::yield_now();
+        }
+    }
+
+    ///
+    /// Frees the block once every slot from start on has been read, or leaves it to the reader still using a slot
+    ///
+    unsafe fn destroy(block: *mut Block<T>, start: usize) {
+        // The last slot's reader started the destruction, so it does not need marking
+        let slots = &(*block).slots;
+        for slot in &slots[start..BLOCK_CAP - 1] {
+            if slot.state.load(Ordering::Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0
+            {
+                return;
+            }
+        }
+        drop(Box::from_raw(block));
+    }
+}
+
+impl<T> Slot<T> {
+    fn wait_write(&self) {
+        // On a single core the sender we are waiting on may need our time slice, so yield rather than spin
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            thread::yield_now();
+        }
+    }
+}
+
+impl<T> Channel<T> {
+    fn push(&self, message: T) {
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+
+        loop {
+            let offset = tail % LAP;
+            if offset == BLOCK_CAP {
+                // Another sender is installing the next block
+                thread::yield_now();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // Allocate before claiming the last slot, so the other senders are not kept waiting on the allocator
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+
+            // SeqCst pairs with the receivers' SeqCst fence before they load tail
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                tail + 1,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = next_block.take().unwrap();
+                        self.tail.block.store(next, Ordering::Release);
+                        self.tail.index.fetch_add(1, Ordering::Release);
+                        (*block).next.store(next, Ordering::Release);
+                    }
+
+                    let slot = &(*block).slots[offset];
+                    (*slot.message.get()).write(message);
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+                    break;
+                },
+                Err(current) => {
+                    tail = current;
+                    block = self.tail.block.load(Ordering::Acquire);
+                }
+            }
+        }
+
+        if let Some(unused) = next_block {
+            // Safety: never shared, another sender installed the next block
+            drop(unsafe { Box::from_raw(unused) });
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+
+        loop {
+            let offset = head % LAP;
+            if offset == BLOCK_CAP {
+                // Another receiver is moving head onto the next block
+                thread::yield_now();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            atomic::fence(Ordering::SeqCst);
+            if head == self.tail.index.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            match self.head.index.compare_exchange_weak(
+                head,
+                head + 1,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        // A sender has claimed the last slot, so the next block is on its way
+                        let next = (*block).wait_next();
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(head + 2, Ordering::Release);
+                    }
+
+                    let slot = &(*block).slots[offset];
+                    slot.wait_write();
+                    let message = (*slot.message.get()).assume_init_read();
+
+                    if offset + 1 == BLOCK_CAP {
+                        Block::destroy(block, 0);
+                    } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+                    return Some(message);
+                },
+                Err(current) => {
+                    head = current;
+                    block = self.head.block.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+
+    ///
+    /// Unparks up to count sleeping receivers
+    ///
+    fn wake(&self, count: usize) {
+        if self.sleeping.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let mut waiters = self.waiters.lock().unwrap();
+        for _ in 0..count {
+            match waiters.pop() {
+                Some(thread) => thread.unpark(),
+                None => break,
+            }
+        }
+    }
+
+    fn wake_all(&self) {
+        for thread in self.waiters.lock().unwrap().drain(..) {
+            thread.unpark();
+        }
+    }
+
+    fn has_messages(&self) -> bool {
+        atomic::fence(Ordering::SeqCst);
+        let head = self.head.index.load(Ordering::Relaxed);
+        head != self.tail.index.load(Ordering::Relaxed)
+    }
+
+    fn receive(&self) -> Option<T> {
+        loop {
+            if let Some(message) = self.pop() {
+                return Some(message);
+            }
+
+            if self.senders.load(Ordering::Acquire) == 0 {
+                // The last sends happened-before the last Sender dropped, so one more look will find them
+                return self.pop();
+            }
+
+            let current = thread::current();
+            self.waiters.lock().unwrap().push(current.clone());
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+
+            // Check again after counting ourselves, a sender that pushed before seeing the count will not unpark us
+            if !self.has_messages() && self.senders.load(Ordering::SeqCst) > 0 {
+                thread::park();
+            }
+
+            self.sleeping.fetch_sub(1, Ordering::Relaxed);
+            self.waiters
+                .lock()
+                .unwrap()
+                .retain(|thread| thread.id() != current.id());
+        }
+    }
+
+    fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.index.load(Ordering::SeqCst);
+            let head = self.head.index.load(Ordering::SeqCst);
+            if self.tail.index.load(Ordering::SeqCst) == tail {
+                // Take the extra position of each block out of the count
+                let slots = |index: usize| index - index / LAP;
+                return slots(tail).saturating_sub(slots(head));
+            }
+        }
+    }
+
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Relaxed)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) {
+        self.channel.push(message);
+        self.channel.wake(1);
+    }
+
+    ///
+    /// Sends every message, then wakes up to one sleeping receiver per message. Returns how many were sent.
+    ///
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        let mut count = 0;
+        for message in messages {
+            self.channel.push(message);
+            count += 1;
+        }
+        self.channel.wake(count);
+        count
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, blocks are added as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Last sender, every receiver needs to see the channel is disconnected
+            self.channel.wake_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    ///
+    /// True if receive() would not block, either a message is queued or every Sender has been dropped
+    ///
+    pub fn is_ready(&self) -> bool {
+        self.channel.has_messages() || self.channel.senders.load(Ordering::Relaxed) == 0
+    }
+
+    ///
+    /// Returns None straight away if the queue is empty
+    ///
+    pub fn try_receive(&self) -> Option<T> {
+        self.channel.pop()
+    }
+
+    ///
+    /// Parks the thread whilst the queue is empty. Returns None once every Sender has been dropped and the queue is drained.
+    ///
+    pub fn receive(&self) -> Option<T> {
+        self.channel.receive()
+    }
+
+    ///
+    /// Blocks till there is at least one message, then takes up to max messages that are already queued. Returns 0 once every Sender has been dropped and the queue is drained.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        match self.receive() {
+            Some(message) => {
+                buffer.push(message);
+                let before = buffer.len();
+                buffer.extend((1..max).map_while(|_| self.try_receive()));
+                1 + buffer.len() - before
+            }
+            None => 0,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, blocks are added as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.0.index.get_mut();
+        let tail = *self.tail.0.index.get_mut();
+        let mut block = *self.head.0.block.get_mut();
+
+        // Safety: we have exclusive access, every position from head to tail holds a written message or marks the end of a block
+        unsafe {
+            while head != tail {
+                let offset = head % LAP;
+                if offset < BLOCK_CAP {
+                    (*(*block).slots[offset].message.get()).assume_init_drop();
+                } else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head += 1;
+            }
+            drop(Box::from_raw(block));
+        }
+    }
+}
+
+pub fn channel_linked_blocks_main() {
+    let (sender, receiver) = channel::<String>();
+
+    thread::scope(|s| {
+        for id in 0..3 {
+            let receiver = receiver.clone();
+            s.spawn(move || {
+                let count = receiver.iter().count();
+                println!("worker {id} received {count} messages");
+            });
+        }
+        drop(receiver);
+
+        for id in 0..3 {
+            let sender = sender.clone();
+            s.spawn(move || {
+                // A burst bigger than a block
+                for i in 0..100 {
+                    sender.send(format!("producer {id} message {i}"));
+                }
+            });
+        }
+        drop(sender);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, BLOCK_CAP};
+    use crate::test_util::DetectDrop;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn messages_cross_block_boundaries_in_order() {
+        let (sender, receiver) = channel();
+        let count = BLOCK_CAP * 3 + 5;
+        for i in 0..count {
+            sender.send(i);
+        }
+        assert_eq!(receiver.len(), count);
+
+        let mut batch = Vec::new();
+        assert_eq!(
+            receiver.receive_batch(BLOCK_CAP + 1, &mut batch),
+            BLOCK_CAP + 1
+        );
+        assert_eq!(sender.len(), count - BLOCK_CAP - 1);
+        drop(sender);
+        assert!(receiver.is_closed());
+
+        batch.extend(receiver.iter());
+        assert_eq!(batch, (0..count).collect::<Vec<_>>());
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn every_message_is_received_once_by_many_receivers() {
+        let (sender, receiver) = channel();
+        let received = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let receiver = receiver.clone();
+                let received = &received;
+                s.spawn(move || {
+                    for message in receiver {
+                        received.lock().unwrap().push(message);
+                    }
+                });
+            }
+            assert_eq!(receiver.receiver_count(), 5);
+            drop(receiver);
+
+            // Let the receivers park before anything is sent
+            thread::sleep(Duration::from_millis(20));
+            for p in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..500 {
+                        sender.send(p * 1000 + i);
+                    }
+                });
+            }
+            drop(sender);
+        });
+
+        let mut received = received.into_inner().unwrap();
+        received.sort();
+        let expected: Vec<_> = (0..4)
+            .flat_map(|p| (0..500).map(move |i| p * 1000 + i))
+            .collect();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn unreceived_messages_and_blocks_are_dropped_with_the_channel() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = channel();
+        for _ in 0..BLOCK_CAP * 2 {
+            sender.send(DetectDrop(num_drops.clone()));
+        }
+
+        for _ in 0..BLOCK_CAP + 3 {
+            drop(receiver.receive());
+        }
+        assert_eq!(num_drops.load(Ordering::Relaxed), BLOCK_CAP + 3);
+
+        drop(sender);
+        drop(receiver);
+        assert_eq!(num_drops.load(Ordering::Relaxed), BLOCK_CAP * 2);
+    }
+}
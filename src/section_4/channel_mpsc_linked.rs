@@ -0,0 +1,545 @@
+use std::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+};
+
+use super::{IntoIter, Iter, Receive, SpinLock, TryIter};
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    message: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(message: Option<T>) -> *mut Node<T> {
+        Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            message,
+        }))
+    }
+}
+
+enum Pop<T> {
+    Data(T),
+    Empty,
+    // A sender has swapped itself in as the newest node, but has not linked the previous node to it yet
+    Inconsistent,
+}
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<T> {
+    // Newest node, senders swap their node in here
+    newest: AtomicPtr<Node<T>>,
+    // Oldest node, only touched by the single Receiver. Its message has already been taken (or it is the starting stub node)
+    oldest: UnsafeCell<*mut Node<T>>,
+    senders: AtomicUsize,
+    receiver_dropped: AtomicBool,
+    // Counted up before a node is added and down after it is taken, so it can run ahead of the list but never below it
+    len: AtomicUsize,
+    receiver_waiting: AtomicBool,
+    receiving_thread: SpinLock<Option<Thread>>,
+}
+
+unsafe impl<T> Send for Channel<T> where T: Send {}
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+/**
+ * An unbounded multiple producer single consumer channel, using a linked list of nodes (Dmitry Vyukov's intrusive MPSC queue).
+ *
+ * Sending never takes a lock:
+ * - The sender boxes the message into a new node
+ * - swap() the newest pointer to our node, which gives back the previous newest node. Only one sender can ever get a given previous node back, so no compare_exchange loop is needed
+ * - link the previous node's next pointer to our node with a Release store
+ *
+ * Between the swap and the link, the list is briefly broken (the Inconsistent state). The receiver will see that the newest pointer has moved on, but the next pointer is still null, so it spins till the sender links the node.
+ *
+ * The receiver follows the next pointers from the oldest node, which only it touches, so it needs no atomics of its own. When the list is empty it parks the thread (section_1/thread_parking.rs), the thread is stored behind the SpinLock from section_4/spin_lock.rs so the Receiver is free to be moved to another thread, and senders only take the lock if the receiver has flagged that it is about to park.
+ *
+ * Senders can be cloned, and when the last Sender is dropped, receive() returns None once the queue is drained.
+ *
+ * len() and the endpoint counts are read from atomics, there is only ever one Receiver so receiver_count() is 1 till it is dropped. The channel is closed once every Sender or the Receiver has been dropped.
+ *
+ * send_batch() links the whole batch into a chain of nodes first, while nobody else can see it, and then adds the chain with a single swap and a single wake up of the receiver.
+ */
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+// Does not implement Sync, there can only be a single consumer
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    _no_sync: PhantomData<Cell<()>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let stub = Node::new(None);
+    let arc = Arc::new(Channel {
+        newest: AtomicPtr::new(stub),
+        oldest: UnsafeCell::new(stub),
+        senders: AtomicUsize::new(1),
+        receiver_dropped: AtomicBool::new(false),
+        len: AtomicUsize::new(0),
+        receiver_waiting: AtomicBool::new(false),
+        receiving_thread: SpinLock::new(None),
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver {
+            channel: arc,
+            _no_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Channel<T> {
+    fn push(&self, message: T) {
+        let node = Node::new(Some(message));
+        self.len.fetch_add(1, Ordering::Relaxed);
+        // SeqCst pairs with the receiver's SeqCst store of receiver_waiting and load of newest, so either the receiver sees our node or we see that it is waiting
+        let previous = self.newest.swap(node, Ordering::SeqCst);
+        // Safety: the previous node is only freed by the receiver after it has followed its next pointer, which we have not set yet
+        unsafe { (*previous).next.store(node, Ordering::Release) };
+    }
+
+    fn push_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        let mut messages = messages.into_iter();
+        let Some(first) = messages.next() else {
+            return 0;
+        };
+
+        let first = Node::new(Some(first));
+        let mut last = first;
+        let mut count = 1;
+        for message in messages {
+            let node = Node::new(Some(message));
+            // Safety: the chain is not shared yet, only this thread can see it
+            unsafe { (*last).next.store(node, Ordering::Relaxed) };
+            last = node;
+            count += 1;
+        }
+
+        // Same as push(), with the chain standing in for a single node
+        self.len.fetch_add(count, Ordering::Relaxed);
+        let previous = self.newest.swap(last, Ordering::SeqCst);
+        unsafe { (*previous).next.store(first, Ordering::Release) };
+        count
+    }
+
+    // Safety: must only be called by the single Receiver
+    unsafe fn pop(&self) -> Pop<T> {
+        let oldest = *self.oldest.get();
+        let next = (*oldest).next.load(Ordering::Acquire);
+
+        if !next.is_null() {
+            *self.oldest.get() = next;
+            let message = (*next).message.take().unwrap();
+            drop(Box::from_raw(oldest));
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            return Pop::Data(message);
+        }
+
+        if self.newest.load(Ordering::Acquire) == oldest {
+            Pop::Empty
+        } else {
+            Pop::Inconsistent
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        if self.receiver_dropped.load(Ordering::Relaxed) {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+
+    fn wake_receiver(&self) {
+        if self.receiver_waiting.swap(false, Ordering::SeqCst) {
+            if let Some(thread) = &*self.receiving_thread.lock() {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) {
+        self.channel.push(message);
+        self.channel.wake_receiver();
+    }
+
+    ///
+    /// Sends every message with one swap, returns how many were sent
+    ///
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        let count = self.channel.push_batch(messages);
+        if count > 0 {
+            self.channel.wake_receiver();
+        }
+        count
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, the list grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Last sender, wake the receiver so it can see the channel is disconnected
+            self.channel.wake_receiver();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    ///
+    /// True if receive() would not block, either a message is queued or every Sender has been dropped
+    ///
+    pub fn is_ready(&self) -> bool {
+        let oldest = unsafe { *self.channel.oldest.get() };
+        self.channel.newest.load(Ordering::Relaxed) != oldest
+            || self.channel.senders.load(Ordering::Relaxed) == 0
+    }
+
+    ///
+    /// Returns None straight away if the queue is empty. Only spins if a sender is half way through linking its node.
+    ///
+    pub fn try_receive(&self) -> Option<T> {
+        loop {
+            // Safety: Receiver is not Sync or Clone, so this is the only consumer
+            match unsafe { self.channel.pop() } {
+                Pop::Data(message) => return Some(message),
+                Pop::Empty => return None,
+                Pop::Inconsistent => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    ///
+    /// Parks the thread whilst the queue is empty. Returns None once every Sender has been dropped and the queue is drained.
+    ///
+    pub fn receive(&self) -> Option<T> {
+        loop {
+            if let Some(message) = self.try_receive() {
+                return Some(message);
+            }
+
+            if self.channel.senders.load(Ordering::Acquire) == 0 {
+                // The last sends happened-before the last Sender dropped, so one more look will find them
+                return self.try_receive();
+            }
+
+            *self.channel.receiving_thread.lock() = Some(thread::current());
+            self.channel.receiver_waiting.store(true, Ordering::SeqCst);
+
+            // Check again after flagging, a sender that pushed before seeing the flag will not unpark us
+            let oldest = unsafe { *self.channel.oldest.get() };
+            if self.channel.newest.load(Ordering::SeqCst) == oldest
+                && self.channel.senders.load(Ordering::SeqCst) > 0
+            {
+                thread::park();
+            }
+            self.channel
+                .receiver_waiting
+                .store(false, Ordering::Relaxed);
+        }
+    }
+
+    ///
+    /// Blocks till there is at least one message, then takes up to max messages that are already queued. Returns 0 once every Sender has been dropped and the queue is drained.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        match self.receive() {
+            Some(message) => {
+                buffer.push(message);
+                let before = buffer.len();
+                buffer.extend((1..max).map_while(|_| self.try_receive()));
+                1 + buffer.len() - before
+            }
+            None => 0,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, the list grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.receiver_dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let mut node = *self.oldest.get_mut();
+        while !node.is_null() {
+            // Safety: every node in the list was leaked from a Box, and we have exclusive access
+            let mut boxed = unsafe { Box::from_raw(node) };
+            node = *boxed.next.get_mut();
+        }
+    }
+}
+
+pub fn channel_mpsc_linked_main() {
+    let (sender, receiver) = channel::<String>();
+
+    thread::scope(|s| {
+        for id in 0..3 {
+            let sender = sender.clone();
+            s.spawn(move || {
+                for i in 0..3 {
+                    sender.send(format!("producer {id} message {i}"));
+                }
+            });
+        }
+        // Only the clones should keep the channel open
+        drop(sender);
+
+        let mut count = 0;
+        for message in &receiver {
+            println!("chan_msg {:?}", message);
+            count += 1;
+        }
+        assert_eq!(count, 9);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+    use crate::test_util::DetectDrop;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn messages_from_each_sender_arrive_in_order() {
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            for p in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        sender.send((p, i));
+                    }
+                });
+            }
+        });
+        drop(sender);
+
+        let mut last = [None; 4];
+        let mut count = 0;
+        for (p, i) in receiver {
+            assert!(last[p].is_none_or(|l| l < i));
+            last[p] = Some(i);
+            count += 1;
+        }
+        assert_eq!(count, 4000);
+    }
+
+    #[test]
+    fn receiver_parks_till_a_message_is_sent() {
+        let (sender, receiver) = channel();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            sender.send("hello world!");
+        });
+
+        assert_eq!(receiver.try_receive(), None);
+        assert_eq!(receiver.receive(), Some("hello world!"));
+        // The only sender is dropped at the end of the thread
+        assert_eq!(receiver.receive(), None);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn unreceived_messages_are_dropped_with_the_channel() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = channel();
+        sender.send(DetectDrop(num_drops.clone()));
+        sender.send(DetectDrop(num_drops.clone()));
+
+        drop(receiver.receive());
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+
+        drop(sender);
+        drop(receiver);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn a_batch_arrives_in_order_with_one_wake_up() {
+        let (sender, receiver) = channel();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(sender.send_batch(0..10), 10);
+            assert_eq!(sender.send_batch([]), 0);
+        });
+
+        let mut buffer = Vec::new();
+        while receiver.receive_batch(4, &mut buffer) > 0 {}
+        assert_eq!(buffer, (0..10).collect::<Vec<_>>());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn reports_length_and_endpoint_counts() {
+        let (sender, receiver) = channel();
+        let other = sender.clone();
+        assert_eq!(receiver.capacity(), None);
+        assert_eq!(receiver.sender_count(), 2);
+
+        sender.send_batch(0..3);
+        other.send(3);
+        assert_eq!(receiver.len(), 4);
+        receiver.try_receive();
+        assert_eq!(sender.len(), 3);
+
+        drop(receiver);
+        assert_eq!(sender.receiver_count(), 0);
+        assert!(other.is_closed());
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{IntoIter, Iter, Receive, TryIter};
+
+// A queued message, ordered by priority and then by the order it was sent in
+struct Entry<P, T> {
+    priority: P,
+    sequence: u64,
+    message: T,
+}
+
+struct Queue<P, T> {
+    heap: BinaryHeap<Entry<P, T>>,
+    // Sequence number the next message will get, so equal priorities come out in the order they went in
+    next_sequence: u64,
+}
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<P, T> {
+    queue: Mutex<Queue<P, T>>,
+    message_ready: Condvar,
+    senders: AtomicUsize,
+    receiver_dropped: AtomicBool,
+    // Copy of the heap length, written whilst holding the lock so len() can read it without locking
+    len: AtomicUsize,
+}
+
+/**
+ * section_4/channel_vec_dequeue.rs hands messages out in the order they were sent, so an urgent control message waits behind all the bulk work queued before it.
+ *
+ * Here every message is sent with a priority, and receive() always returns the highest priority message that is pending. The VecDeque is swapped for a BinaryHeap (a max heap, so the largest priority is at the top), and it is still a Mutex with a Condvar to put the receiver to sleep.
+ *
+ * A BinaryHeap on its own does not keep the order of equal items, so each message is also numbered with an increasing sequence number. Messages with the same priority compare by sequence number, lowest first, which keeps them FIFO.
+ *
+ * The Senders are counted like the channel() in section_4/channel_vec_dequeue.rs, so receive() returns None once every Sender has been dropped and the queue is empty. The length and endpoint counts are kept in atomics as well, so they can be read without the lock.
+ */
+pub struct Sender<P, T> {
+    channel: Arc<Channel<P, T>>,
+}
+
+pub struct Receiver<P, T> {
+    channel: Arc<Channel<P, T>>,
+}
+
+pub fn channel<P: Ord, T>() -> (Sender<P, T>, Receiver<P, T>) {
+    let arc = Arc::new(Channel {
+        queue: Mutex::new(Queue {
+            heap: BinaryHeap::new(),
+            next_sequence: 0,
+        }),
+        message_ready: Condvar::new(),
+        senders: AtomicUsize::new(1),
+        receiver_dropped: AtomicBool::new(false),
+        len: AtomicUsize::new(0),
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver { channel: arc },
+    )
+}
+
+impl<P: Ord, T> PartialEq for Entry<P, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<P: Ord, T> Eq for Entry<P, T> {}
+
+impl<P: Ord, T> PartialOrd for Entry<P, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: Ord, T> Ord for Entry<P, T> {
+    // Higher priority first, then the lower (older) sequence number first
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl<P: Ord, T> Channel<P, T> {
+    fn pop(&self, queue: &mut Queue<P, T>) -> Option<T> {
+        let entry = queue.heap.pop();
+        self.len.store(queue.heap.len(), Ordering::Relaxed);
+        entry.map(|entry| entry.message)
+    }
+}
+
+impl<P, T> Channel<P, T> {
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        if self.receiver_dropped.load(Ordering::Relaxed) {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+impl<P: Ord, T> Sender<P, T> {
+    ///
+    /// Higher priorities are received first
+    ///
+    pub fn send(&self, priority: P, message: T) {
+        let mut queue = self.channel.queue.lock().unwrap();
+        let sequence = queue.next_sequence;
+        queue.next_sequence += 1;
+        queue.heap.push(Entry {
+            priority,
+            sequence,
+            message,
+        });
+        self.channel.len.store(queue.heap.len(), Ordering::Relaxed);
+        drop(queue);
+
+        self.channel.message_ready.notify_one();
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, the heap grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<P, T> Clone for Sender<P, T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<P, T> Drop for Sender<P, T> {
+    fn drop(&mut self) {
+        if self.channel.senders.fetch_sub(1, Ordering::Release) == 1 {
+            drop(self.channel.queue.lock().unwrap());
+            self.channel.message_ready.notify_all();
+        }
+    }
+}
+
+impl<P: Ord, T> Receiver<P, T> {
+    pub fn try_receive(&self) -> Option<T> {
+        let mut queue = self.channel.queue.lock().unwrap();
+        self.channel.pop(&mut queue)
+    }
+
+    ///
+    /// Blocks till there is a message, returns None once every Sender has been dropped and the queue is empty
+    ///
+    pub fn receive(&self) -> Option<T> {
+        let mut queue = self.channel.queue.lock().unwrap();
+        loop {
+            if let Some(message) = self.channel.pop(&mut queue) {
+                return Some(message);
+            }
+
+            if self.channel.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+
+            queue = self.channel.message_ready.wait(queue).unwrap();
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        !self.channel.queue.lock().unwrap().heap.is_empty()
+            || self.channel.senders.load(Ordering::Relaxed) == 0
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.channel.len() == 0
+    }
+
+    ///
+    /// None, the heap grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<P, T> Drop for Receiver<P, T> {
+    fn drop(&mut self) {
+        self.channel.receiver_dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<P: Ord, T> Receive for Receiver<P, T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<P: Ord, T> IntoIterator for Receiver<P, T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, P: Ord, T> IntoIterator for &'a Receiver<P, T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<P, T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<P, T>> {
+        Iter::new(self)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Bulk,
+    Control,
+}
+
+pub fn channel_priority_main() {
+    let (sender, receiver) = channel::<Priority, String>();
+
+    thread::scope(|s| {
+        let control = sender.clone();
+        s.spawn(move || {
+            for i in 0..5 {
+                sender.send(Priority::Bulk, format!("work {i}"));
+            }
+        });
+
+        s.spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            control.send(Priority::Control, "pause".to_string());
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        // pause overtakes all the work that was queued before it
+        for message in &receiver {
+            println!("received {message}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+    use std::thread;
+
+    #[test]
+    fn highest_priority_is_received_first() {
+        let (sender, receiver) = channel();
+        sender.send(1, "low");
+        sender.send(5, "high");
+        sender.send(3, "medium");
+
+        assert_eq!(receiver.len(), 3);
+        assert_eq!(receiver.receive(), Some("high"));
+        assert_eq!(sender.len(), 2);
+        assert_eq!(receiver.receive(), Some("medium"));
+        assert_eq!(receiver.receive(), Some("low"));
+        assert_eq!(receiver.try_receive(), None);
+    }
+
+    #[test]
+    fn equal_priorities_keep_the_order_they_were_sent_in() {
+        let (sender, receiver) = channel();
+        for i in 0..10 {
+            sender.send(i % 2, i);
+        }
+        drop(sender);
+        assert!(receiver.is_closed());
+        assert_eq!(receiver.sender_count(), 0);
+
+        assert_eq!(
+            receiver.into_iter().collect::<Vec<_>>(),
+            [1, 3, 5, 7, 9, 0, 2, 4, 6, 8]
+        );
+    }
+
+    #[test]
+    fn receive_waits_for_senders_on_other_threads() {
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            for p in 0..3 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..10 {
+                        sender.send(p, p * 10 + i);
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut received: Vec<i32> = receiver.iter().collect();
+            received.sort();
+            assert_eq!(received, (0..30).collect::<Vec<_>>());
+        });
+    }
+}
@@ -0,0 +1,514 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use super::{IntoIter, Iter, Receive, TryIter};
+
+// One blocked send or receive, waiting to be paired with the other side
+struct Packet<T> {
+    message: Mutex<Option<T>>,
+    // Set by the other side once the message has been handed over
+    done: AtomicBool,
+    thread: Thread,
+}
+
+struct WaitQueues<T> {
+    senders: VecDeque<Arc<Packet<T>>>,
+    receivers: VecDeque<Arc<Packet<T>>>,
+}
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<T> {
+    waiting: Mutex<WaitQueues<T>>,
+    // Number of Sender handles, once it reaches zero receivers stop waiting. Only changed whilst holding the waiting lock, but readable without it
+    senders: AtomicUsize,
+    // The same for Receiver handles, once it reaches zero senders stop waiting
+    receivers: AtomicUsize,
+}
+
+/**
+ * A zero capacity (rendezvous) channel, there is no queue of messages, so send() does not return till a receiver has taken the message. Both threads meet at the handoff, which makes it a synchronisation point as well as a channel.
+ *
+ * Each blocked send or receive is a Packet with the waiting thread's handle, kept in a queue of waiting senders or waiting receivers:
+ * - send() first looks for a waiting receiver. If there is one, it puts the message in that receiver's packet, sets done with Release and unparks the receiver
+ * - otherwise it queues its own packet holding the message, and parks till a receiver takes the message and sets done
+ * - receive() is the mirror image, it takes the message out of a waiting sender's packet, or queues an empty packet and parks till a sender fills it
+ *
+ * The Mutex only guards the two wait queues for the moment it takes to pair up, the parking is done with thread::park() like section_4/channel_blocking.rs, with done checked after every wake up as park() can return spuriously.
+ *
+ * With a timeout, a thread that runs out of time takes the lock and removes its packet. If the packet is not in the queue any more, the other side has already paired with it and is about to set done, so we wait for that and the handoff still counts.
+ *
+ * When the last Sender is dropped, every waiting receiver's packet is completed without a message, so receive() returns None instead of waiting forever. When the last Receiver is dropped, every waiting sender's packet is completed with its message still in it, and send() gives the message back as Err.
+ *
+ * Nothing is ever queued, so len() is always 0 and capacity() is Some(0). The endpoint counts are atomics, so they can be read without the lock. The channel is closed once every Sender or every Receiver has been dropped.
+ */
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let arc = Arc::new(Channel {
+        waiting: Mutex::new(WaitQueues {
+            senders: VecDeque::new(),
+            receivers: VecDeque::new(),
+        }),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver { channel: arc },
+    )
+}
+
+impl<T> Packet<T> {
+    fn new(message: Option<T>) -> Arc<Packet<T>> {
+        Arc::new(Packet {
+            message: Mutex::new(message),
+            done: AtomicBool::new(false),
+            thread: thread::current(),
+        })
+    }
+
+    fn complete(&self) {
+        self.done.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+
+    ///
+    /// Parks till the other side completes the packet, or the deadline passes. Returns true if the packet was completed.
+    ///
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        while !self.done.load(Ordering::Acquire) {
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<T> Channel<T> {
+    ///
+    /// Takes our packet back out of the wait queue after a timeout. If it is not there, the other side has it, so wait for the handoff to finish.
+    ///
+    fn cancel(
+        &self,
+        packet: &Arc<Packet<T>>,
+        queue: impl FnOnce(&mut WaitQueues<T>) -> &mut VecDeque<Arc<Packet<T>>>,
+    ) -> bool {
+        let mut waiting = self.waiting.lock().unwrap();
+        let queue = queue(&mut waiting);
+        if let Some(index) = queue.iter().position(|p| Arc::ptr_eq(p, packet)) {
+            queue.remove(index);
+            return true;
+        }
+        drop(waiting);
+
+        packet.wait(None);
+        false
+    }
+}
+
+impl<T> Channel<T> {
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Relaxed)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+impl<T> Sender<T> {
+    fn send_deadline(&self, message: T, deadline: Option<Instant>) -> Result<(), T> {
+        let mut waiting = self.channel.waiting.lock().unwrap();
+        if let Some(receiver) = waiting.receivers.pop_front() {
+            drop(waiting);
+            *receiver.message.lock().unwrap() = Some(message);
+            receiver.complete();
+            return Ok(());
+        }
+
+        // Every Receiver has been dropped, nothing will ever take the message
+        if self.channel.receivers.load(Ordering::Relaxed) == 0 {
+            return Err(message);
+        }
+
+        let packet = Packet::new(Some(message));
+        waiting.senders.push_back(packet.clone());
+        drop(waiting);
+
+        if !packet.wait(deadline) {
+            self.channel.cancel(&packet, |w| &mut w.senders);
+        }
+        // A receiver takes the message out of the packet. It is still there if we timed out, or the last Receiver was dropped
+        let message = packet.message.lock().unwrap().take();
+        match message {
+            None => Ok(()),
+            Some(message) => Err(message),
+        }
+    }
+
+    ///
+    /// Blocks till a receiver has taken the message, gives the message back once every Receiver has been dropped
+    ///
+    pub fn send(&self, message: T) -> Result<(), T> {
+        self.send_deadline(message, None)
+    }
+
+    ///
+    /// Gives the message back if no receiver took it in time
+    ///
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), T> {
+        self.send_deadline(message, Some(Instant::now() + timeout))
+    }
+
+    ///
+    /// Always 0, a message is handed straight over and never queued
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        0
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        true
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Receiver<T> {
+    fn receive_deadline(&self, deadline: Option<Instant>) -> Option<T> {
+        let mut waiting = self.channel.waiting.lock().unwrap();
+        if let Some(sender) = waiting.senders.pop_front() {
+            drop(waiting);
+            let message = sender.message.lock().unwrap().take();
+            sender.complete();
+            return message;
+        }
+
+        if self.channel.senders.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+
+        let packet = Packet::new(None);
+        waiting.receivers.push_back(packet.clone());
+        drop(waiting);
+
+        if packet.wait(deadline) || !self.channel.cancel(&packet, |w| &mut w.receivers) {
+            let message = packet.message.lock().unwrap().take();
+            return message;
+        }
+        None
+    }
+
+    ///
+    /// Blocks till a sender hands over a message, returns None once every Sender has been dropped
+    ///
+    #[allow(unused)]
+    pub fn receive(&self) -> Option<T> {
+        self.receive_deadline(None)
+    }
+
+    ///
+    /// Only takes a message from a sender that is already waiting
+    ///
+    #[allow(unused)]
+    pub fn try_receive(&self) -> Option<T> {
+        let sender = self.channel.waiting.lock().unwrap().senders.pop_front()?;
+        let message = sender.message.lock().unwrap().take();
+        sender.complete();
+        message
+    }
+
+    pub fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        self.receive_deadline(Some(Instant::now() + timeout))
+    }
+
+    ///
+    /// True if a sender is already waiting, so receive() would not block
+    ///
+    pub fn is_ready(&self) -> bool {
+        let waiting = self.channel.waiting.lock().unwrap();
+        !waiting.senders.is_empty() || self.channel.senders.load(Ordering::Relaxed) == 0
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    ///
+    /// Always 0, a message is handed straight over and never queued
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        0
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        true
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let waiting = self.channel.waiting.lock().unwrap();
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        drop(waiting);
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut waiting = self.channel.waiting.lock().unwrap();
+        if self.channel.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // Wake every waiting receiver with an empty packet
+            for receiver in waiting.receivers.drain(..) {
+                receiver.complete();
+            }
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let waiting = self.channel.waiting.lock().unwrap();
+        self.channel.receivers.fetch_add(1, Ordering::Relaxed);
+        drop(waiting);
+        Receiver {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut waiting = self.channel.waiting.lock().unwrap();
+        if self.channel.receivers.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // Wake every waiting sender, with its message left in the packet to be handed back
+            for sender in waiting.senders.drain(..) {
+                sender.complete();
+            }
+        }
+    }
+}
+
+pub fn channel_rendezvous_main() {
+    let (sender, receiver) = channel::<&str>();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            thread::sleep(Duration::from_secs(1));
+            println!("receiver ready");
+            let chan_msg = receiver.receive_timeout(Duration::from_secs(5));
+            println!("chan_msg {:?}", chan_msg);
+        });
+
+        println!("sending...");
+        // Blocks till the receiver has woken up and taken the message
+        sender.send("hello world!").unwrap();
+        println!("sent");
+
+        // Nobody is receiving any more, so this gives the message back
+        let unsent = sender.send_timeout("anyone there?", Duration::from_millis(100));
+        assert_eq!(unsent, Err("anyone there?"));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn send_waits_for_the_receiver() {
+        let (sender, receiver) = channel();
+        let received = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                received.store(true, Ordering::Relaxed);
+                assert_eq!(receiver.receive(), Some("hello world!"));
+            });
+
+            sender.send("hello world!").unwrap();
+            assert!(received.load(Ordering::Relaxed));
+        });
+    }
+
+    #[test]
+    fn timeouts_give_up_without_a_partner() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            sender.send_timeout("hello", Duration::from_millis(20)),
+            Err("hello")
+        );
+        assert_eq!(receiver.receive_timeout(Duration::from_millis(20)), None);
+
+        // Neither timed out attempt is left behind in the channel
+        assert!(!receiver.is_ready());
+        assert_eq!(receiver.receive_timeout(Duration::from_millis(20)), None);
+        assert_eq!(sender.len(), 0);
+        assert_eq!(receiver.capacity(), Some(0));
+
+        let other = receiver.clone();
+        assert_eq!(sender.receiver_count(), 2);
+        drop(receiver);
+        drop(other);
+        assert!(sender.is_closed());
+    }
+
+    #[test]
+    fn every_message_is_handed_over_once() {
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            for p in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..100 {
+                        sender.send(p * 100 + i).unwrap();
+                    }
+                });
+            }
+
+            drop(sender);
+
+            let mut received: Vec<i32> = receiver.iter().collect();
+            received.sort();
+            assert_eq!(received, (0..400).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn dropping_every_receiver_fails_waiting_senders() {
+        let (sender, receiver) = channel();
+        let other = receiver.clone();
+
+        thread::scope(|s| {
+            let blocked = s.spawn(|| sender.send(String::from("hello")));
+
+            thread::sleep(Duration::from_millis(20));
+            drop(receiver);
+            // One Receiver is left, the sender keeps waiting
+            thread::sleep(Duration::from_millis(20));
+            assert!(!blocked.is_finished());
+
+            drop(other);
+            assert_eq!(blocked.join().unwrap(), Err(String::from("hello")));
+        });
+
+        // Nothing left to pair with, so it does not wait at all
+        assert_eq!(sender.send(String::from("bye")), Err(String::from("bye")));
+        assert!(sender.is_closed());
+    }
+}
@@ -0,0 +1,935 @@
+use std::{
+    cell::UnsafeCell,
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use super::{IntoIter, Iter, Receive, TryIter};
+
+struct Slot<T> {
+    /// Which lap of the ring the slot is on, tells senders and receivers if the slot is free or holds a message
+    sequence: AtomicUsize,
+    message: UnsafeCell<MaybeUninit<T>>,
+    /// Set when a Reservation is dropped without being committed, the slot is published with no message in it so receivers skip it
+    abandoned: UnsafeCell<bool>,
+}
+
+/**
+ * From section_4/channel_vec_dequeue.rs, the main problem was every send and receive locking the same Mutex, so threads were serialised behind each other even when they touched different ends of the queue.
+ *
+ * Here we pre-allocate a fixed array of slots, and use two AtomicUsize counters instead of a lock
+ * - tail is the position the next sender will write to
+ * - head is the position the next receiver will read from
+ *
+ * Positions only ever increase, the slot index is position % capacity. Each slot has its own sequence number, which is how a thread knows the state of that slot without a lock:
+ * - sequence == position, the slot is empty and a sender on this lap can claim it
+ * - sequence == position + 1, the slot holds a message and a receiver on this lap can claim it
+ * - sequence == position + capacity, the receiver has emptied the slot, so it is free for the sender on the next lap
+ *
+ * The last two can only be told apart if capacity is more than 1, so new() needs at least 2 slots.
+ *
+ * A thread claims a position with compare_exchange on head/ tail (so only one thread gets each position), and then publishes the slot with a Release store to the sequence. The other side reads the sequence with Acquire, the same Release-Acquire pairing as section_3/release_acquire.rs, so the message write happens-before the read of the message.
+ *
+ * The queue is bounded, send() will spin then yield the thread whilst the queue is full, and receive() does the same whilst it is empty. try_send() and try_receive() return straight away instead.
+ *
+ * Large messages can be written and read in place, instead of being moved into and out of the slot:
+ * - reserve() claims a position and hands back a Reservation, the caller writes into its MaybeUninit<T> slot and then commit() publishes it (send() is reserve() plus write())
+ * - read() claims a filled slot and hands back a ReadGuard that derefs to the message, the slot is released when the guard is dropped
+ *
+ * Once a position is claimed, receivers will wait for that slot, so a Reservation dropped without commit() still has to publish it. It marks the slot abandoned, and receivers release an abandoned slot and move on to the next position.
+ *
+ * There is no lock to share across a batch, every message still claims its own slot, so send_batch() and receive_batch() are loops over send() and try_receive(). They keep the same API as section_4/channel_vec_dequeue.rs, so the two queues can be swapped for each other.
+ */
+pub struct Channel<T> {
+    buffer: Box<[Slot<T>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Channel<T> {
+    ///
+    /// capacity must be at least 2. With one slot, a full slot's sequence (position + 1) is the same as an empty slot's on the next lap, so a sender would overwrite the unread message
+    ///
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity >= 2, "capacity must be at least 2");
+
+        Self {
+            buffer: (0..capacity)
+                .map(|i| Slot {
+                    sequence: AtomicUsize::new(i),
+                    message: UnsafeCell::new(MaybeUninit::uninit()),
+                    abandoned: UnsafeCell::new(false),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    ///
+    /// Will place the message in the next free slot, or give the message back if the queue is full
+    ///
+    pub fn try_send(&self, message: T) -> Result<(), T> {
+        match self.try_reserve() {
+            Some(reservation) => {
+                reservation.write(message);
+                Ok(())
+            }
+            None => Err(message),
+        }
+    }
+
+    ///
+    /// Claims the next free slot for the caller to write into, None if the queue is full
+    ///
+    pub fn try_reserve(&self) -> Option<Reservation<'_, T>> {
+        let capacity = self.buffer.len();
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[tail % capacity];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence.wrapping_sub(tail) as isize;
+
+            if diff == 0 {
+                // The slot is free on this lap, try to claim the position
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // We won the position, no other thread touches this slot till the Reservation publishes it
+                        return Some(Reservation {
+                            channel: self,
+                            position: tail,
+                        });
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if diff < 0 {
+                // The receiver for the previous lap has not emptied this slot, the queue is full
+                return None;
+            } else {
+                // Another sender claimed this position, catch up
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    ///
+    /// Will take the message from the next filled slot, or return None if the queue is empty
+    ///
+    pub fn try_receive(&self) -> Option<T> {
+        self.try_read().map(ReadGuard::into_inner)
+    }
+
+    ///
+    /// Claims the next filled slot to be read in place, or returns None if the queue is empty
+    ///
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let capacity = self.buffer.len();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[head % capacity];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence.wrapping_sub(head.wrapping_add(1)) as isize;
+
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // Safety: the Acquire load of the sequence synchronises with the sender's Release store, and we won the position
+                        let abandoned = unsafe { mem::take(&mut *slot.abandoned.get()) };
+                        if abandoned {
+                            // Nothing was written, hand the slot back and try the next position
+                            self.release(head);
+                            head = self.head.load(Ordering::Relaxed);
+                            continue;
+                        }
+                        return Some(ReadGuard {
+                            channel: self,
+                            position: head,
+                        });
+                    }
+                    Err(current) => head = current,
+                }
+            } else if diff < 0 {
+                // No sender has filled this slot yet, the queue is empty
+                return None;
+            } else {
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    ///
+    /// True if the slot at head holds a message, so receive() would not block. Another receiver may still take it first.
+    ///
+    pub fn is_ready(&self) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let slot = &self.buffer[head % self.buffer.len()];
+        slot.sequence.load(Ordering::Relaxed) == head.wrapping_add(1)
+    }
+
+    ///
+    /// Positions that have been claimed but not yet received, a send or receive that is half way through still counts
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        // head first, tail never moves backwards so it cannot be behind the head we read
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(self.buffer.len())
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.buffer.len())
+    }
+
+    fn slot(&self, position: usize) -> &Slot<T> {
+        &self.buffer[position % self.buffer.len()]
+    }
+
+    // Marks the slot at position as filled (or abandoned) for the receiver on this lap
+    fn publish(&self, position: usize) {
+        self.slot(position)
+            .sequence
+            .store(position.wrapping_add(1), Ordering::Release);
+    }
+
+    // Marks the slot at position as free for the sender on the next lap
+    fn release(&self, position: usize) {
+        self.slot(position)
+            .sequence
+            .store(position.wrapping_add(self.buffer.len()), Ordering::Release);
+    }
+
+    ///
+    /// Blocks whilst the queue is full, spinning for a short while before yielding the thread back to the OS scheduler
+    ///
+    pub fn send(&self, mut message: T) {
+        let mut step = 0;
+        loop {
+            match self.try_send(message) {
+                Ok(()) => return,
+                Err(m) => message = m,
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Blocks whilst the queue is empty, spinning for a short while before yielding the thread back to the OS scheduler
+    ///
+    pub fn receive(&self) -> T {
+        let mut step = 0;
+        loop {
+            if let Some(message) = self.try_receive() {
+                return message;
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Blocks whilst the queue is full, then claims the next free slot
+    ///
+    #[allow(unused)]
+    pub fn reserve(&self) -> Reservation<'_, T> {
+        let mut step = 0;
+        loop {
+            if let Some(reservation) = self.try_reserve() {
+                return reservation;
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Blocks whilst the queue is empty, then claims the next filled slot
+    ///
+    #[allow(unused)]
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut step = 0;
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Sends every message, blocking whilst the queue is full. Returns how many were sent.
+    ///
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        messages
+            .into_iter()
+            .map(|message| self.send(message))
+            .count()
+    }
+
+    ///
+    /// Blocks till there is at least one message, then takes up to max messages that are already queued. Returns how many were moved into buffer.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        buffer.push(self.receive());
+        1 + drain(self, max - 1, buffer)
+    }
+}
+
+// Takes up to max messages without blocking
+fn drain<T>(channel: &Channel<T>, max: usize, buffer: &mut Vec<T>) -> usize {
+    let before = buffer.len();
+    buffer.extend((0..max).map_while(|_| channel.try_receive()));
+    buffer.len() - before
+}
+
+///
+/// A claimed slot, write the message into slot() then commit(). Dropping it without committing publishes an empty slot that receivers skip.
+///
+pub struct Reservation<'a, T> {
+    channel: &'a Channel<T>,
+    position: usize,
+}
+
+impl<T> Reservation<'_, T> {
+    pub fn slot(&mut self) -> &mut MaybeUninit<T> {
+        // Safety: the position is ours till we publish it
+        unsafe { &mut *self.channel.slot(self.position).message.get() }
+    }
+
+    ///
+    /// Publishes the slot to receivers
+    ///
+    /// # Safety
+    /// The slot must have been initialised through slot()
+    ///
+    pub unsafe fn commit(self) {
+        self.channel.publish(self.position);
+        mem::forget(self);
+    }
+
+    pub fn write(mut self, message: T) {
+        self.slot().write(message);
+        // Safety: we have just written the message
+        unsafe { self.commit() };
+    }
+}
+
+impl<T> Drop for Reservation<'_, T> {
+    fn drop(&mut self) {
+        // Anything written to the slot without a commit is leaked, not dropped, as we cannot know if it was initialised
+        unsafe { *self.channel.slot(self.position).abandoned.get() = true };
+        self.channel.publish(self.position);
+    }
+}
+
+///
+/// A claimed filled slot, derefs to the message in place. The message is dropped and the slot released when the guard is dropped.
+///
+pub struct ReadGuard<'a, T> {
+    channel: &'a Channel<T>,
+    position: usize,
+}
+
+impl<T> ReadGuard<'_, T> {
+    ///
+    /// Moves the message out and releases the slot
+    ///
+    pub fn into_inner(self) -> T {
+        // Safety: the slot holds a message till we release it, and forget() stops Drop dropping it again
+        let message =
+            unsafe { (*self.channel.slot(self.position).message.get()).assume_init_read() };
+        self.channel.release(self.position);
+        mem::forget(self);
+        message
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { (*self.channel.slot(self.position).message.get()).assume_init_ref() }
+    }
+}
+
+impl<T> DerefMut for ReadGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { (*self.channel.slot(self.position).message.get()).assume_init_mut() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { (*self.channel.slot(self.position).message.get()).assume_init_drop() };
+        self.channel.release(self.position);
+    }
+}
+
+fn backoff(step: &mut u32) {
+    if *step < 6 {
+        for _ in 0..1 << *step {
+            std::hint::spin_loop();
+        }
+        *step += 1;
+    } else {
+        thread::yield_now();
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        // Drop any messages that were sent but never received
+        while self.try_receive().is_some() {}
+    }
+}
+
+// The Channel shared between Senders and Receivers, plus the number of Senders so receivers know when to stop waiting
+struct Shared<T> {
+    channel: Channel<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Relaxed)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+/**
+ * Same as the split in section_4/channel_vec_dequeue.rs, channel() shares the ring through an Arc between cloneable Senders and Receivers (both sides can be cloned, it is a multi producer multi consumer queue).
+ *
+ * There is no lock to check the sender count under, so the receiver checks it in between its spins, and once it sees zero it makes one last try_receive(). Anything sent before the last Sender dropped happens-before the Release decrement, so that last look will find it.
+ *
+ * The Receivers are counted as well, so either end can report how many of each are left. The channel is closed once every Sender or every Receiver has been dropped. A Sender waiting on a full queue checks the receiver count in between its spins the same way, and gives the message back once it is zero, as nothing would ever free a slot.
+ */
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[allow(unused)]
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let arc = Arc::new(Shared {
+        channel: Channel::new(capacity),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: arc.clone(),
+        },
+        Receiver { shared: arc },
+    )
+}
+
+impl<T> Sender<T> {
+    #[allow(unused)]
+    pub fn try_send(&self, message: T) -> Result<(), T> {
+        self.shared.channel.try_send(message)
+    }
+
+    ///
+    /// Blocks whilst the queue is full, gives the message back once every Receiver has been dropped
+    ///
+    #[allow(unused)]
+    pub fn send(&self, mut message: T) -> Result<(), T> {
+        let mut step = 0;
+        loop {
+            match self.shared.channel.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(m) => message = m,
+            }
+            if self.shared.receiver_count() == 0 {
+                return Err(message);
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Sends every message, blocking whilst the queue is full. Stops once every Receiver has been dropped, and returns how many were sent.
+    ///
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        messages
+            .into_iter()
+            .map_while(|message| self.send(message).ok())
+            .count()
+    }
+
+    #[allow(unused)]
+    pub fn try_reserve(&self) -> Option<Reservation<'_, T>> {
+        self.shared.channel.try_reserve()
+    }
+
+    ///
+    /// Blocks whilst the queue is full, then claims the next free slot. None once every Receiver has been dropped.
+    ///
+    #[allow(unused)]
+    pub fn reserve(&self) -> Option<Reservation<'_, T>> {
+        let mut step = 0;
+        loop {
+            if let Some(reservation) = self.shared.channel.try_reserve() {
+                return Some(reservation);
+            }
+            if self.shared.receiver_count() == 0 {
+                return None;
+            }
+            backoff(&mut step);
+        }
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Receiver<T> {
+    #[allow(unused)]
+    pub fn try_receive(&self) -> Option<T> {
+        self.shared.channel.try_receive()
+    }
+
+    ///
+    /// Blocks whilst the queue is empty, returns None once every Sender has been dropped and the queue is drained
+    ///
+    #[allow(unused)]
+    pub fn receive(&self) -> Option<T> {
+        let mut step = 0;
+        loop {
+            if let Some(message) = self.shared.channel.try_receive() {
+                return Some(message);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return self.shared.channel.try_receive();
+            }
+            backoff(&mut step);
+        }
+    }
+
+    #[allow(unused)]
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        self.shared.channel.try_read()
+    }
+
+    ///
+    /// Blocks whilst the queue is empty, then reads the next message in place. None once every Sender has been dropped and the queue is drained.
+    ///
+    #[allow(unused)]
+    pub fn read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut step = 0;
+        loop {
+            if let Some(guard) = self.shared.channel.try_read() {
+                return Some(guard);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return self.shared.channel.try_read();
+            }
+            backoff(&mut step);
+        }
+    }
+
+    ///
+    /// Blocks till there is at least one message, then takes up to max messages that are already queued. Returns 0 once every Sender has been dropped and the queue is drained.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        match self.receive() {
+            Some(message) => {
+                buffer.push(message);
+                1 + drain(&self.shared.channel, max - 1, buffer)
+            }
+            None => 0,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn is_ready(&self) -> bool {
+        self.shared.channel.is_ready() || self.shared.senders.load(Ordering::Relaxed) == 0
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+pub fn channel_ring_buffer_main() {
+    let channel = Channel::<usize>::new(8);
+    let total = AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        for producer in 0..4 {
+            let channel = &channel;
+            s.spawn(move || {
+                for i in 0..100 {
+                    channel.send(producer * 100 + i);
+                }
+            });
+        }
+
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    total.fetch_add(channel.receive(), Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    let total = total.into_inner();
+    println!("total {:?}", total);
+    assert_eq!(total, (0..400).sum());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Channel};
+    use crate::test_util::DetectDrop;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    #[test]
+    fn messages_are_received_in_order_and_full_queue_rejects() {
+        let channel = Channel::new(2);
+        assert!(channel.try_receive().is_none());
+
+        channel.send(1);
+        channel.send(2);
+        assert_eq!(channel.try_send(3), Err(3));
+
+        assert_eq!(channel.receive(), 1);
+        channel.send(3);
+        assert_eq!(channel.receive(), 2);
+        assert_eq!(channel.receive(), 3);
+        assert!(channel.try_receive().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be at least 2")]
+    fn capacity_of_one_is_rejected() {
+        // One slot would let a second try_send() overwrite the first message, and Drop would never finish draining it
+        let _ = Channel::<i32>::new(1);
+    }
+
+    #[test]
+    fn every_message_is_received_once_across_threads() {
+        let channel = Channel::new(4);
+        let received = AtomicUsize::new(0);
+        let sum = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for p in 0..4 {
+                let channel = &channel;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        channel.send(p * 1000 + i);
+                    }
+                });
+            }
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        sum.fetch_add(channel.receive(), Ordering::Relaxed);
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(received.load(Ordering::Relaxed), 4000);
+        assert_eq!(sum.load(Ordering::Relaxed), (0..4000).sum());
+    }
+
+    #[test]
+    fn unreceived_messages_are_dropped_with_the_channel() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let channel = Channel::new(4);
+        channel.send(DetectDrop(num_drops.clone()));
+        channel.send(DetectDrop(num_drops.clone()));
+        channel.send(DetectDrop(num_drops.clone()));
+
+        drop(channel.receive());
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+
+        drop(channel);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn receivers_iterate_till_every_sender_is_dropped() {
+        let (sender, receiver) = channel(4);
+        let sum = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for p in 0..2 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..500 {
+                        sender.send(p * 500 + i).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+
+            for _ in 0..2 {
+                let receiver = receiver.clone();
+                s.spawn(|| {
+                    for message in receiver {
+                        sum.fetch_add(message, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(sum.load(Ordering::Relaxed), (0..1000).sum());
+        assert_eq!(receiver.try_iter().next(), None);
+    }
+
+    #[test]
+    fn batches_take_only_what_is_queued() {
+        let (sender, receiver) = channel(8);
+        assert_eq!(sender.send_batch(0..5), 5);
+
+        let mut buffer = Vec::new();
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 3);
+        drop(sender);
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 2);
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 0);
+        assert_eq!(buffer, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reserved_slots_are_written_and_read_in_place() {
+        let (sender, receiver) = channel(3);
+
+        let mut reservation = sender.reserve().unwrap();
+        reservation.slot().write(vec![1, 2, 3]);
+        unsafe { reservation.commit() };
+
+        // Abandoned, receivers skip it
+        drop(sender.reserve().unwrap());
+        sender.try_reserve().unwrap().write(vec![4]);
+
+        let mut guard = receiver.read().unwrap();
+        guard.push(4);
+        assert_eq!(*guard, [1, 2, 3, 4]);
+        drop(guard);
+        assert_eq!(receiver.try_receive(), Some(vec![4]));
+        assert!(receiver.try_read().is_none());
+
+        // Every slot is usable again
+        for i in 0..3 {
+            assert_eq!(sender.try_send(vec![i]), Ok(()));
+        }
+        assert_eq!(sender.try_send(vec![3]), Err(vec![3]));
+    }
+
+    #[test]
+    fn reports_length_and_endpoint_counts() {
+        let (sender, receiver) = channel(4);
+        let other = receiver.clone();
+        assert_eq!(sender.capacity(), Some(4));
+        assert_eq!(sender.receiver_count(), 2);
+        assert!(receiver.is_empty());
+
+        for i in 0..4 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.len(), 4);
+        assert_eq!(sender.try_send(4), Err(4));
+        other.receive();
+        assert_eq!(sender.len(), 3);
+
+        drop(sender);
+        assert!(receiver.is_closed());
+        assert_eq!(other.sender_count(), 0);
+        // Closed, but the queued messages can still be received
+        assert_eq!(other.len(), 3);
+    }
+
+    #[test]
+    fn a_sender_blocked_on_a_full_queue_fails_once_every_receiver_is_dropped() {
+        let (sender, receiver) = channel(2);
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+
+        thread::scope(|s| {
+            let blocked = s.spawn(|| sender.send(2));
+            let batch = s.spawn(|| sender.send_batch(3..5));
+            drop(receiver);
+            assert_eq!(blocked.join().unwrap(), Err(2));
+            assert_eq!(batch.join().unwrap(), 0);
+        });
+        assert!(sender.reserve().is_none());
+    }
+}
@@ -1,17 +1,24 @@
 use std::{
     cell::UnsafeCell,
+    future::Future,
     mem::MaybeUninit,
+    pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    task::{Context, Poll},
     thread,
 };
 
+use super::AtomicWaker;
+
 // Private impl, pub fn exists to return tuple pair of Sender, Receiver
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    // Woken by send(), for a Receiver that is being awaited
+    waker: AtomicWaker,
 }
 
 pub struct Sender<T> {
@@ -28,6 +35,7 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let arc = Arc::new(Channel {
         message: UnsafeCell::new(MaybeUninit::<T>::uninit()),
         ready: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
     });
     (
         Sender {
@@ -42,6 +50,7 @@ impl<T> Sender<T> {
     pub fn send(self, message: T) {
         unsafe { (*self.channel.message.get()).write(message) };
         self.channel.ready.store(true, Ordering::Release);
+        self.channel.waker.wake();
     }
 }
 
@@ -59,6 +68,26 @@ impl<T> Receiver<T> {
     }
 }
 
+/**
+ * Lets async code .await the Receiver (e.g. with section_4/block_on.rs) instead of parking till is_ready().
+ *
+ * poll() registers the task's Waker before checking ready a second time. send() sets ready before waking, so either the second check sees the message, or the Waker is already registered for send() to wake.
+ */
+impl<T> Future for Receiver<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        if !self.is_ready() {
+            self.channel.waker.register(cx.waker());
+            if !self.is_ready() {
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(self.receive())
+    }
+}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         if *self.ready.get_mut() {
@@ -1,12 +1,21 @@
 use std::{
     collections::VecDeque,
-    sync::{Condvar, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
 };
 
+use super::{CancelToken, Cancelled, IntoIter, Iter, Receive, TryIter};
+
 #[allow(unused)]
 pub struct Channel<T> {
     queue: Mutex<VecDeque<T>>,
     item_ready: Condvar,
+    // Receivers blocked on item_ready, only changed whilst holding the queue lock
+    waiting: AtomicUsize,
+    // Copy of the queue length, written whilst holding the lock so len() can read it without locking
+    len: AtomicUsize,
 }
 
 /**
@@ -24,35 +33,516 @@ pub struct Channel<T> {
  *
  * crossbeam-channel crate allows multiple consumers.
  *
+ * send_batch() and receive_batch() cut down on the locking, a whole batch is moved under a single lock. send_batch() then wakes one waiting receiver per message, or all of them if there are more messages than waiters.
+ *
+ * receive_cancellable() takes a CancelToken from section_4/cancel.rs, cancelling it takes the queue lock and notifies every waiting receiver so they can return Err(Cancelled).
+ *
  *
  *
  */
 
 impl<T> Channel<T> {
     #[allow(unused)]
-    pub fn new(&self) -> Self {
+    pub fn new() -> Self {
         Self {
             queue: Mutex::new(VecDeque::<T>::new()),
             item_ready: Condvar::new(),
+            waiting: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
         }
     }
 
     #[allow(unused)]
     pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
+        let mut guard = self.queue.lock().unwrap();
+        guard.push_back(message);
+        self.len.store(guard.len(), Ordering::Relaxed);
+        drop(guard);
         self.item_ready.notify_one();
     }
 
+    ///
+    /// Queues every message under one lock, returns how many were sent
+    ///
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        let mut guard = self.queue.lock().unwrap();
+        let before = guard.len();
+        guard.extend(messages);
+        let sent = guard.len() - before;
+        self.len.store(guard.len(), Ordering::Relaxed);
+        let waiting = self.waiting.load(Ordering::Relaxed);
+        drop(guard);
+
+        if sent >= waiting {
+            self.item_ready.notify_all();
+        } else {
+            for _ in 0..sent {
+                self.item_ready.notify_one();
+            }
+        }
+        sent
+    }
+
+    #[allow(unused)]
+    pub fn is_ready(&self) -> bool {
+        !self.queue.lock().unwrap().is_empty()
+    }
+
     #[allow(unused)]
     pub fn receive(&self) -> T {
         let mut guard = self.queue.lock().unwrap();
         loop {
-            if let Some(message) = guard.pop_front() {
+            if let Some(message) = self.pop(&mut guard) {
                 return message;
             }
 
             // block thread in loop till there is a message in the queue to return
-            guard = self.item_ready.wait(guard).unwrap();
+            guard = self.wait(guard);
+        }
+    }
+
+    ///
+    /// Same as receive(), but returns Err(Cancelled) once the token is cancelled from another thread
+    ///
+    #[allow(unused)]
+    pub fn receive_cancellable(&self, token: &CancelToken) -> Result<T, Cancelled>
+    where
+        T: Send,
+    {
+        token.scope(&|| self.notify_cancelled(), || {
+            let mut guard = self.queue.lock().unwrap();
+            loop {
+                if let Some(message) = self.pop(&mut guard) {
+                    return Ok(message);
+                }
+                if token.is_cancelled() {
+                    return Err(Cancelled);
+                }
+                guard = self.wait(guard);
+            }
+        })
+    }
+
+    ///
+    /// Blocks till there is at least one message, then moves up to max messages into buffer under one lock. Returns how many were moved.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        let mut guard = self.queue.lock().unwrap();
+        while guard.is_empty() {
+            guard = self.wait(guard);
         }
+        self.drain(&mut guard, max, buffer)
+    }
+
+    ///
+    /// Number of queued messages, read without taking the lock so it may already be out of date
+    ///
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///
+    /// None, the queue grows as needed
+    ///
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    fn pop(&self, queue: &mut VecDeque<T>) -> Option<T> {
+        let message = queue.pop_front();
+        self.len.store(queue.len(), Ordering::Relaxed);
+        message
+    }
+
+    fn drain(&self, queue: &mut VecDeque<T>, max: usize, buffer: &mut Vec<T>) -> usize {
+        let count = max.min(queue.len());
+        buffer.extend(queue.drain(..count));
+        self.len.store(queue.len(), Ordering::Relaxed);
+        count
+    }
+
+    // Taking the lock first means a receiver is either waiting, or has not checked the token yet
+    fn notify_cancelled(&self) {
+        drop(self.queue.lock().unwrap());
+        self.item_ready.notify_all();
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, VecDeque<T>>) -> MutexGuard<'a, VecDeque<T>> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let guard = self.item_ready.wait(guard).unwrap();
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        guard
+    }
+}
+
+// The Channel shared between Sender and Receiver, plus the number of Senders so the Receiver knows when to stop waiting
+struct Shared<T> {
+    channel: Channel<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Relaxed)
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Relaxed)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender_count() == 0 || self.receiver_count() == 0
+    }
+}
+
+/**
+ * The Channel above can only be shared by reference, and receive() has no way to know that nothing will ever be sent again, so a consumer looping on it blocks forever.
+ *
+ * channel() splits it into cloneable Senders and a Receiver, sharing the Channel through an Arc (like section_4/channel_sender_receiver.rs). The Senders are counted, and when the last one is dropped it notifies every waiting receiver, which then returns None once the queue is drained. This is what lets the Receiver be used as an iterator.
+ *
+ * The count is checked whilst holding the queue lock, and the last Sender takes the lock before notifying, so a receiver cannot check the count and then miss the notification.
+ *
+ * Both ends can report the queue length and how many Senders and Receivers are left, from atomics so monitoring never contends for the queue lock. The channel is closed once every Sender or the Receiver has been dropped.
+ */
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[allow(unused)]
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let arc = Arc::new(Shared {
+        channel: Channel::new(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: arc.clone(),
+        },
+        Receiver { shared: arc },
+    )
+}
+
+impl<T> Sender<T> {
+    #[allow(unused)]
+    pub fn send(&self, message: T) {
+        self.shared.channel.send(message);
+    }
+
+    #[allow(unused)]
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> usize {
+        self.shared.channel.send_batch(messages)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::Release) == 1 {
+            drop(self.shared.channel.queue.lock().unwrap());
+            self.shared.channel.item_ready.notify_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    #[allow(unused)]
+    pub fn try_receive(&self) -> Option<T> {
+        let mut guard = self.shared.channel.queue.lock().unwrap();
+        self.shared.channel.pop(&mut guard)
+    }
+
+    ///
+    /// Blocks till there is a message, returns None once every Sender has been dropped and the queue is empty
+    ///
+    #[allow(unused)]
+    pub fn receive(&self) -> Option<T> {
+        let mut guard = self.shared.channel.queue.lock().unwrap();
+        loop {
+            if let Some(message) = self.shared.channel.pop(&mut guard) {
+                return Some(message);
+            }
+
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+
+            guard = self.shared.channel.wait(guard);
+        }
+    }
+
+    ///
+    /// Same as receive(), but returns Err(Cancelled) once the token is cancelled from another thread
+    ///
+    #[allow(unused)]
+    pub fn receive_cancellable(&self, token: &CancelToken) -> Result<Option<T>, Cancelled>
+    where
+        T: Send,
+    {
+        let channel = &self.shared.channel;
+        token.scope(&|| channel.notify_cancelled(), || {
+            let mut guard = channel.queue.lock().unwrap();
+            loop {
+                if let Some(message) = channel.pop(&mut guard) {
+                    return Ok(Some(message));
+                }
+                if self.shared.senders.load(Ordering::Acquire) == 0 {
+                    return Ok(None);
+                }
+                if token.is_cancelled() {
+                    return Err(Cancelled);
+                }
+                guard = channel.wait(guard);
+            }
+        })
+    }
+
+    ///
+    /// Blocks till there is at least one message, then moves up to max messages into buffer. Returns 0 once every Sender has been dropped and the queue is empty.
+    ///
+    #[allow(unused)]
+    pub fn receive_batch(&self, max: usize, buffer: &mut Vec<T>) -> usize {
+        if max == 0 {
+            return 0;
+        }
+
+        let mut guard = self.shared.channel.queue.lock().unwrap();
+        while guard.is_empty() {
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return 0;
+            }
+            guard = self.shared.channel.wait(guard);
+        }
+        self.shared.channel.drain(&mut guard, max, buffer)
+    }
+
+    #[allow(unused)]
+    pub fn is_ready(&self) -> bool {
+        !self.shared.channel.queue.lock().unwrap().is_empty()
+            || self.shared.senders.load(Ordering::Relaxed) == 0
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn try_iter(&self) -> TryIter<'_, Self> {
+        TryIter::new(self)
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.shared.channel.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.shared.channel.is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.channel.capacity()
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        self.shared.sender_count()
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receiver_count()
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> Receive for Receiver<T> {
+    type Message = T;
+
+    fn receive(&self) -> Option<T> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.try_receive()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, Receiver<T>>;
+
+    fn into_iter(self) -> Iter<'a, Receiver<T>> {
+        Iter::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Channel};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn iter_ends_once_every_sender_is_dropped() {
+        let (sender, receiver) = channel();
+
+        thread::scope(|s| {
+            for p in 0..3 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..10 {
+                        sender.send(p * 10 + i);
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut received: Vec<i32> = receiver.iter().collect();
+            received.sort();
+            assert_eq!(received, (0..30).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn try_iter_drains_without_blocking() {
+        let (sender, receiver) = channel();
+        sender.send(1);
+        sender.send(2);
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(receiver.try_iter().next(), None);
+
+        sender.send(3);
+        drop(sender);
+        assert_eq!(receiver.into_iter().collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn batches_are_sent_and_drained_together() {
+        let (sender, receiver) = channel();
+        assert_eq!(sender.send_batch(0..5), 5);
+
+        let mut buffer = Vec::new();
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 3);
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 2);
+        assert_eq!(buffer, [0, 1, 2, 3, 4]);
+
+        drop(sender);
+        assert_eq!(receiver.receive_batch(3, &mut buffer), 0);
+    }
+
+    #[test]
+    fn reports_length_and_endpoint_counts() {
+        let (sender, receiver) = channel();
+        let other = sender.clone();
+        assert_eq!(sender.capacity(), None);
+        assert_eq!(receiver.sender_count(), 2);
+        assert_eq!(sender.receiver_count(), 1);
+
+        sender.send_batch([1, 2, 3]);
+        assert_eq!(receiver.len(), 3);
+        receiver.try_receive();
+        assert_eq!(sender.len(), 2);
+
+        drop(other);
+        assert!(!sender.is_closed());
+        drop(receiver);
+        assert!(sender.is_closed());
+        assert_eq!(sender.receiver_count(), 0);
+    }
+
+    #[test]
+    fn send_batch_wakes_every_waiting_receiver() {
+        let channel = Channel::new();
+
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..3).map(|_| s.spawn(|| channel.receive())).collect();
+
+            // Give the receivers time to block on the Condvar
+            thread::sleep(Duration::from_millis(50));
+            channel.send_batch([1, 2, 3]);
+
+            let mut received: Vec<i32> = receivers.into_iter().map(|t| t.join().unwrap()).collect();
+            received.sort();
+            assert_eq!(received, [1, 2, 3]);
+        });
     }
 }
@@ -0,0 +1,244 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, RwLock, RwLockReadGuard,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The Sender has been dropped, so the value will not change again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Closed;
+
+// Private impl, pub fn exists to return tuple pair of Sender, Receiver
+struct Channel<T> {
+    value: RwLock<T>,
+    // Bumped on every send, whilst the write lock is still held, so a reader holding the read lock sees a version that matches the value
+    version: AtomicU64,
+    closed: AtomicBool,
+    // Only used to put receivers to sleep in changed(), the value itself is behind the RwLock
+    waiting: Mutex<()>,
+    value_changed: Condvar,
+}
+
+/**
+ * section_2/progress_reporting_atomic.rs shares progress through an AtomicI32 plus unpark(), which only works for state that fits in an atomic. A watch channel holds a single value of any type, and only ever the latest one.
+ *
+ * - The Sender replaces the value (or modifies it in place) behind a RwLock, so many receivers can read it at the same time
+ * - Every send bumps a version counter
+ * - Each Receiver remembers the last version it has seen. changed() blocks till the version moves past it, then takes the newest version, so an update is never reported twice, and several quick updates are reported once (receivers only care about the latest value)
+ *
+ * borrow() gives read access to the current value without marking it as seen, borrow_and_update() marks it as seen as well.
+ *
+ * changed() sleeps on a Condvar. The Sender takes the waiting Mutex before notifying, so a receiver that has checked the version but not yet started waiting cannot miss the notification (same reasoning as section_1/thread_condvar.rs).
+ */
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+    seen_version: u64,
+}
+
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let arc = Arc::new(Channel {
+        value: RwLock::new(initial),
+        version: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        waiting: Mutex::new(()),
+        value_changed: Condvar::new(),
+    });
+    (
+        Sender {
+            channel: arc.clone(),
+        },
+        Receiver {
+            channel: arc,
+            seen_version: 0,
+        },
+    )
+}
+
+impl<T> Channel<T> {
+    fn notify(&self) {
+        drop(self.waiting.lock().unwrap());
+        self.value_changed.notify_all();
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        self.send_modify(|current| *current = value);
+    }
+
+    ///
+    /// Modifies the value in place, useful when T is expensive to rebuild (e.g. bumping a counter in a larger struct)
+    ///
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        let mut guard = self.channel.value.write().unwrap();
+        modify(&mut guard);
+        self.channel.version.fetch_add(1, Ordering::Release);
+        drop(guard);
+
+        self.channel.notify();
+    }
+
+    #[allow(unused)]
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.channel.value.read().unwrap()
+    }
+
+    ///
+    /// New receivers have already seen the current value
+    ///
+    #[allow(unused)]
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver {
+            channel: self.channel.clone(),
+            seen_version: self.channel.version.load(Ordering::Acquire),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.closed.store(true, Ordering::Release);
+        self.channel.notify();
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.channel.value.read().unwrap()
+    }
+
+    #[allow(unused)]
+    pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+        let guard = self.channel.value.read().unwrap();
+        self.seen_version = self.channel.version.load(Ordering::Acquire);
+        guard
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.channel.version.load(Ordering::Acquire) != self.seen_version
+    }
+
+    ///
+    /// True if changed() would return straight away
+    ///
+    pub fn is_ready(&self) -> bool {
+        self.has_changed() || self.channel.closed.load(Ordering::Acquire)
+    }
+
+    ///
+    /// Blocks till a version newer than the last one seen is published, Err(Closed) if the Sender is dropped first
+    ///
+    pub fn changed(&mut self) -> Result<(), Closed> {
+        let mut guard = self.channel.waiting.lock().unwrap();
+        loop {
+            let version = self.channel.version.load(Ordering::Acquire);
+            if version != self.seen_version {
+                self.seen_version = version;
+                return Ok(());
+            }
+            if self.channel.closed.load(Ordering::Acquire) {
+                return Err(Closed);
+            }
+            guard = self.channel.value_changed.wait(guard).unwrap();
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver {
+            channel: self.channel.clone(),
+            seen_version: self.seen_version,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Progress {
+    done: usize,
+    current_item: String,
+}
+
+pub fn channel_watch_main() {
+    let (sender, mut receiver) = channel(Progress::default());
+
+    thread::scope(|s| {
+        // A background thread to process all 10 items
+        s.spawn(move || {
+            for i in 0..10 {
+                thread::sleep(Duration::from_millis(100));
+                sender.send(Progress {
+                    done: i + 1,
+                    current_item: format!("item {i}"),
+                });
+            }
+        });
+
+        while receiver.changed().is_ok() {
+            let progress = receiver.borrow();
+            println!(
+                "Working...{}/10 done, last {}",
+                progress.done, progress.current_item
+            );
+        }
+    });
+
+    println!("done");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Closed};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn receivers_see_the_latest_value() {
+        let (sender, receiver) = channel(0);
+        let other = sender.subscribe();
+        assert_eq!(*receiver.borrow(), 0);
+        assert!(!receiver.has_changed());
+
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(*receiver.borrow(), 2);
+        assert_eq!(*other.borrow(), 2);
+        assert!(receiver.has_changed());
+    }
+
+    #[test]
+    fn changed_reports_each_update_once() {
+        let (sender, mut receiver) = channel(String::new());
+
+        sender.send("first".to_string());
+        sender.send_modify(|value| value.push_str(" second"));
+
+        // Both sends are coalesced into one change
+        assert_eq!(receiver.changed(), Ok(()));
+        assert_eq!(*receiver.borrow(), "first second");
+        assert!(!receiver.has_changed());
+
+        drop(sender);
+        assert_eq!(receiver.changed(), Err(Closed));
+    }
+
+    #[test]
+    fn changed_blocks_till_a_new_version_is_sent() {
+        let (sender, mut receiver) = channel(0);
+
+        let t = thread::spawn(move || {
+            assert_eq!(receiver.changed(), Ok(()));
+            *receiver.borrow_and_update()
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        sender.send(42);
+        assert_eq!(t.join().unwrap(), 42);
+    }
+}
@@ -0,0 +1,543 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use super::CachePadded;
+
+struct Slot<T> {
+    // Sequence + 1 of the entry last published here, 0 if nothing has been published yet
+    published: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+struct Ring<T> {
+    slots: Box<[Slot<T>]>,
+    // Next sequence a Producer will claim
+    claimed: CachePadded<AtomicU64>,
+    // One per stage, the next sequence that stage will process (so everything before it is done), or DROPPED
+    cursors: Box<[CachePadded<AtomicU64>]>,
+    // One per stage, the stages it waits for, an empty list waits for the Producers
+    dependencies: Box<[Vec<usize>]>,
+    producers: AtomicUsize,
+}
+
+// The cursor of a Stage that has been dropped
+const DROPPED: u64 = u64::MAX;
+
+unsafe impl<T> Sync for Ring<T> where T: Send + Sync {}
+
+/**
+ * A pipeline built from channels copies every message from one stage's channel into the next, and each channel has its own locks or atomics to fight over. The LMAX disruptor uses a single ring for the whole pipeline instead, and every stage reads the entries in place.
+ *
+ * It is the sequence and publish idea from section_3/release_acquire.rs, done once per stage:
+ * - Producers claim a sequence number with fetch_add on claimed, so many Producers never get the same slot, then fill in the entry and store sequence + 1 into the slot's published with Release (the slot's READY flag)
+ * - Each stage has its own cursor, the next sequence it will process. A stage after the Producers reads published with Acquire, a stage after other stages reads their cursors with Acquire, and it can process everything up to the lowest of them
+ * - After processing a batch, a stage stores its cursor with Release, which publishes "done with these" to the stages after it and to the Producers
+ * - A Producer waits till the slowest stage is less than capacity behind before reusing a slot
+ *
+ * Release-Acquire is transitive, so a stage after stage A sees everything the Producer wrote into an entry, and any changes A made through interior mutability (an atomic in T), before it sees A's cursor move past it.
+ *
+ * The entries are created once (T: Default) and reused, so publish() gives the Producer a &mut T to fill in place. Stages only get a &T, as stages that do not depend on each other read the same entry at the same time.
+ *
+ * A Stage that is dropped, without run() or part way through, sets its cursor to DROPPED. Nothing reads through it any more, so the Producers stop waiting for it, and the stages after it wait for the stages (or Producers) it was waiting for instead. Without that, its cursor would stop moving and the Producers would wait forever once the ring wrapped round to it.
+ *
+ * Stages wait by yielding, as a disruptor stage is expected to be busy. The capacity must be a power of two so a sequence maps to its slot with a mask.
+ */
+pub struct DisruptorBuilder<T> {
+    capacity: usize,
+    // The stages each stage waits for, an empty list waits for the Producers
+    stages: Vec<Vec<usize>>,
+    _marker: PhantomData<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageId(usize);
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    // Lowest stage cursor seen last time, saves reading every cursor on every publish
+    cached_gate: u64,
+}
+
+pub struct Stage<T> {
+    ring: Arc<Ring<T>>,
+    index: usize,
+    next: u64,
+}
+
+impl<T: Default + Send + Sync> DisruptorBuilder<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity.is_power_of_two(),
+            "capacity must be a power of two"
+        );
+        Self {
+            capacity,
+            stages: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    ///
+    /// Adds a stage that processes each entry after every stage in after has, or straight after it is published if after is empty. Panics if after holds an id this builder has not handed out yet, so the stages can never wait for each other in a cycle.
+    ///
+    pub fn stage(&mut self, after: &[StageId]) -> StageId {
+        let count = self.stages.len();
+        self.stages.push(
+            after
+                .iter()
+                .map(|&StageId(index)| {
+                    assert!(
+                        index < count,
+                        "a stage can only come after stages already added to this builder"
+                    );
+                    index
+                })
+                .collect(),
+        );
+        StageId(self.stages.len() - 1)
+    }
+
+    ///
+    /// Allocates the ring, the stages are returned in the order they were added
+    ///
+    pub fn build(self) -> (Producer<T>, Vec<Stage<T>>) {
+        assert!(
+            !self.stages.is_empty(),
+            "a disruptor needs at least one stage"
+        );
+
+        let ring = Arc::new(Ring {
+            slots: (0..self.capacity)
+                .map(|_| Slot {
+                    published: AtomicU64::new(0),
+                    value: UnsafeCell::new(T::default()),
+                })
+                .collect(),
+            claimed: CachePadded(AtomicU64::new(0)),
+            cursors: self
+                .stages
+                .iter()
+                .map(|_| CachePadded(AtomicU64::new(0)))
+                .collect(),
+            dependencies: self.stages.into_boxed_slice(),
+            producers: AtomicUsize::new(1),
+        });
+
+        let stages = (0..ring.cursors.len())
+            .map(|index| Stage {
+                ring: ring.clone(),
+                index,
+                next: 0,
+            })
+            .collect();
+        (
+            Producer {
+                ring,
+                cached_gate: 0,
+            },
+            stages,
+        )
+    }
+}
+
+impl<T> Ring<T> {
+    fn slot(&self, sequence: u64) -> &Slot<T> {
+        &self.slots[sequence as usize & (self.slots.len() - 1)]
+    }
+
+    ///
+    /// The lowest cursor of the stages that are still running, DROPPED if there are none
+    ///
+    fn slowest_cursor(&self) -> u64 {
+        self.cursors
+            .iter()
+            .map(|cursor| cursor.load(Ordering::Acquire))
+            .min()
+            .unwrap()
+    }
+
+    ///
+    /// One past the last sequence a stage waiting for dependencies may process, looking from next
+    ///
+    fn available(&self, dependencies: &[usize], next: u64) -> u64 {
+        if dependencies.is_empty() {
+            // Producers can publish out of order, stop at the first gap
+            let mut end = next;
+            while self.slot(end).published.load(Ordering::Acquire) == end + 1 {
+                end += 1;
+            }
+            end
+        } else {
+            dependencies
+                .iter()
+                .map(|&index| match self.cursors[index].load(Ordering::Acquire) {
+                    // Wait for whatever the dropped stage was waiting for
+                    DROPPED => self.available(&self.dependencies[index], next),
+                    cursor => cursor,
+                })
+                .min()
+                .unwrap()
+        }
+    }
+}
+
+impl<T> Producer<T> {
+    ///
+    /// Claims the next sequence, waiting for the slowest stage if the ring is full, lets fill() update the entry in place, then publishes it. Returns the sequence.
+    ///
+    /// If fill() panics the entry is still published, as it is when fill() returns, so the stages see whatever it had written by then. Leaving the sequence unpublished would stop every stage at it for good.
+    ///
+    pub fn publish(&mut self, fill: impl FnOnce(&mut T)) -> u64 {
+        let ring = &self.ring;
+        let sequence = ring.claimed.fetch_add(1, Ordering::Relaxed);
+        let capacity = ring.slots.len() as u64;
+
+        // saturating_add, as the gate is DROPPED once every stage has been dropped
+        while sequence >= self.cached_gate.saturating_add(capacity) {
+            self.cached_gate = ring.slowest_cursor();
+            if sequence >= self.cached_gate.saturating_add(capacity) {
+                thread::yield_now();
+            }
+        }
+
+        let slot = ring.slot(sequence);
+        let guard = Publish { slot, sequence };
+        // Safety: every stage has moved past the last entry in this slot, and no other Producer has this sequence
+        fill(unsafe { &mut *slot.value.get() });
+        drop(guard);
+        sequence
+    }
+}
+
+// Publishes the claimed sequence when dropped, including when fill() unwinds
+struct Publish<'a, T> {
+    slot: &'a Slot<T>,
+    sequence: u64,
+}
+
+impl<T> Drop for Publish<'_, T> {
+    fn drop(&mut self) {
+        self.slot
+            .published
+            .store(self.sequence + 1, Ordering::Release);
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.ring.producers.fetch_add(1, Ordering::Relaxed);
+        Producer {
+            ring: self.ring.clone(),
+            cached_gate: self.cached_gate,
+        }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.producers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Stage<T> {
+    ///
+    /// One past the last sequence this stage may process
+    ///
+    fn available(&self) -> u64 {
+        self.ring
+            .available(&self.ring.dependencies[self.index], self.next)
+    }
+
+    ///
+    /// Passes every entry that is ready to the handler with its sequence, then moves the cursor past them. Returns how many were processed, never blocks.
+    ///
+    pub fn try_process(&mut self, mut handler: impl FnMut(&T, u64)) -> usize {
+        let end = self.available();
+        for sequence in self.next..end {
+            // Safety: the Producers will not reuse the slot till our cursor moves past it
+            handler(unsafe { &*self.ring.slot(sequence).value.get() }, sequence);
+        }
+
+        let processed = (end - self.next) as usize;
+        if processed > 0 {
+            self.next = end;
+            self.ring.cursors[self.index].store(end, Ordering::Release);
+        }
+        processed
+    }
+
+    ///
+    /// Processes entries till every Producer has been dropped and this stage has caught up
+    ///
+    pub fn run(mut self, mut handler: impl FnMut(&T, u64)) {
+        loop {
+            if self.try_process(&mut handler) > 0 {
+                continue;
+            }
+
+            // The Release in Producer::drop means every claimed sequence has been published
+            if self.ring.producers.load(Ordering::Acquire) == 0
+                && self.next == self.ring.claimed.load(Ordering::Relaxed)
+            {
+                return;
+            }
+            thread::yield_now();
+        }
+    }
+
+    ///
+    /// The next sequence this stage will process
+    ///
+    #[allow(unused)]
+    pub fn cursor(&self) -> u64 {
+        self.next
+    }
+}
+
+impl<T> Drop for Stage<T> {
+    fn drop(&mut self) {
+        // Stop the Producers and the stages after this one waiting for a cursor that will never move again
+        self.ring.cursors[self.index].store(DROPPED, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Trade {
+    id: u64,
+    price: u64,
+    quantity: u64,
+    // Set by the risk stage, read by the settlement stage
+    approved: AtomicU64,
+}
+
+pub fn disruptor_main() {
+    let mut builder = DisruptorBuilder::<Trade>::new(16);
+    // The classic diamond, journal and risk both run on the new trades, settlement waits for both
+    let journal = builder.stage(&[]);
+    let risk = builder.stage(&[]);
+    builder.stage(&[journal, risk]);
+    let (mut producer, stages) = builder.build();
+    let [journal, risk, settlement]: [Stage<Trade>; 3] = stages.try_into().ok().unwrap();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            let mut journalled = 0;
+            journal.run(|_, _| journalled += 1);
+            println!("journalled {journalled} trades");
+        });
+
+        s.spawn(move || {
+            risk.run(|trade, _| {
+                let approved = trade.price * trade.quantity <= 5_000;
+                trade.approved.store(approved as u64, Ordering::Relaxed);
+            })
+        });
+
+        s.spawn(move || {
+            let mut settled = 0;
+            settlement.run(|trade, _| {
+                if trade.approved.load(Ordering::Relaxed) == 1 {
+                    settled += trade.price * trade.quantity;
+                } else {
+                    println!("rejected trade {}", trade.id);
+                }
+            });
+            println!("settled {settled}");
+        });
+
+        for id in 0..100 {
+            producer.publish(|trade| {
+                trade.id = id;
+                trade.price = 10 + id;
+                trade.quantity = 50;
+            });
+        }
+        drop(producer);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisruptorBuilder, Stage};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+    };
+
+    #[derive(Default)]
+    struct Entry {
+        value: u64,
+        doubled: AtomicU64,
+    }
+
+    #[test]
+    fn every_stage_sees_every_entry_in_order() {
+        let mut builder = DisruptorBuilder::<u64>::new(4);
+        builder.stage(&[]);
+        builder.stage(&[]);
+        let (mut producer, stages) = builder.build();
+
+        thread::scope(|s| {
+            for stage in stages {
+                s.spawn(move || {
+                    let mut seen = Vec::new();
+                    stage.run(|&value, sequence| {
+                        assert_eq!(value, sequence * 3);
+                        seen.push(value);
+                    });
+                    assert_eq!(seen, (0..100).map(|i| i * 3).collect::<Vec<_>>());
+                });
+            }
+
+            for i in 0..100 {
+                assert_eq!(producer.publish(|value| *value = i * 3), i);
+            }
+            drop(producer);
+        });
+    }
+
+    #[test]
+    fn a_dependent_stage_sees_the_upstream_stages_changes() {
+        let mut builder = DisruptorBuilder::<Entry>::new(8);
+        let first = builder.stage(&[]);
+        builder.stage(&[first]);
+        let (mut producer, stages) = builder.build();
+        let [mut first, mut second]: [Stage<Entry>; 2] = stages.try_into().ok().unwrap();
+
+        producer.publish(|entry| entry.value = 21);
+        // Nothing for the second stage till the first has processed it
+        assert_eq!(second.try_process(|_, _| {}), 0);
+        assert_eq!(
+            first.try_process(|entry, _| entry.doubled.store(entry.value * 2, Ordering::Relaxed)),
+            1
+        );
+        assert_eq!(
+            second.try_process(|entry, _| assert_eq!(entry.doubled.load(Ordering::Relaxed), 42)),
+            1
+        );
+        assert_eq!(second.cursor(), 1);
+    }
+
+    #[test]
+    fn producers_wait_for_the_slowest_stage() {
+        let mut builder = DisruptorBuilder::<Entry>::new(4);
+        let first = builder.stage(&[]);
+        builder.stage(&[first]);
+        let (producer, stages) = builder.build();
+        let [first, second]: [Stage<Entry>; 2] = stages.try_into().ok().unwrap();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                first.run(|entry, _| entry.doubled.store(entry.value * 2, Ordering::Relaxed))
+            });
+
+            let total = s.spawn(move || {
+                let mut total = 0;
+                second.run(|entry, _| {
+                    assert_eq!(entry.doubled.load(Ordering::Relaxed), entry.value * 2);
+                    total += entry.value;
+                });
+                total
+            });
+
+            for p in 0..3 {
+                let mut producer = producer.clone();
+                s.spawn(move || {
+                    for i in 0..200 {
+                        producer.publish(|entry| entry.value = p * 1000 + i);
+                    }
+                });
+            }
+            drop(producer);
+
+            let expected: u64 = (0..3)
+                .flat_map(|p| (0..200).map(move |i| p * 1000 + i))
+                .sum();
+            assert_eq!(total.join().unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn an_entry_is_published_when_fill_panics() {
+        let mut builder = DisruptorBuilder::<u64>::new(4);
+        builder.stage(&[]);
+        let (mut producer, stages) = builder.build();
+        let [mut stage]: [Stage<u64>; 1] = stages.try_into().ok().unwrap();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            producer.publish(|value| {
+                *value = 1;
+                panic!("fill failed");
+            })
+        }));
+        assert!(result.is_err());
+        producer.publish(|value| *value = 2);
+
+        // The stage is not stuck at the first entry, and sees what fill() wrote before it panicked
+        let mut seen = Vec::new();
+        assert_eq!(stage.try_process(|&value, _| seen.push(value)), 2);
+        assert_eq!(seen, [1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "a stage can only come after stages already added to this builder")]
+    fn a_stage_cannot_come_after_a_stage_from_another_builder() {
+        let mut other = DisruptorBuilder::<u64>::new(4);
+        other.stage(&[]);
+        let foreign = other.stage(&[]);
+
+        let mut builder = DisruptorBuilder::<u64>::new(4);
+        builder.stage(&[foreign]);
+    }
+
+    #[test]
+    fn dropped_stages_do_not_hold_the_ring_up() {
+        let mut builder = DisruptorBuilder::<u64>::new(4);
+        let unused = builder.stage(&[]);
+        builder.stage(&[unused]);
+        let (mut producer, stages) = builder.build();
+        let [unused, mut after_unused]: [Stage<u64>; 2] = stages.try_into().ok().unwrap();
+
+        producer.publish(|value| *value = 0);
+        drop(unused);
+
+        // The stage after it now waits for the Producers, and sees only what has been published
+        assert_eq!(
+            after_unused.try_process(|&value, _| assert_eq!(value, 0)),
+            1
+        );
+        assert_eq!(after_unused.try_process(|_, _| {}), 0);
+
+        thread::scope(|s| {
+            let seen = s.spawn(move || {
+                let mut seen = Vec::new();
+                after_unused.run(|&value, _| seen.push(value));
+                seen
+            });
+
+            // Many times round the ring, without the dropped stage ever moving
+            for i in 1..100 {
+                producer.publish(|value| *value = i);
+            }
+            drop(producer);
+            assert_eq!(seen.join().unwrap(), (1..100).collect::<Vec<_>>());
+        });
+
+        // With every stage dropped, nothing holds the Producers back at all
+        let mut builder = DisruptorBuilder::<u64>::new(2);
+        builder.stage(&[]);
+        let (mut producer, stages) = builder.build();
+        drop(stages);
+        for i in 0..10 {
+            assert_eq!(producer.publish(|value| *value = i), i);
+        }
+    }
+}
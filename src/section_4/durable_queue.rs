@@ -0,0 +1,628 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env, fs,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+};
+
+///
+/// Turns a message into bytes for the segment file and back again
+///
+pub trait Serialize: Sized {
+    fn serialize(&self, out: &mut Vec<u8>);
+
+    ///
+    /// None if the bytes are not a valid message
+    ///
+    fn deserialize(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Serialize for String {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Serialize for Vec<u8> {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Serialize for u64 {
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// A new segment file is started once the current one would grow past this many bytes
+    pub segment_size: u64,
+    /// Call sync_data() after every write, so a record has reached the disk before send() or ack() returns
+    pub sync: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            segment_size: 1024 * 1024,
+            sync: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Delivery<T> {
+    /// Pass to ack() once the message has been handled
+    pub id: u64,
+    pub message: T,
+}
+
+// Record layout: kind (u8), id (u64), payload length (u32), payload, checksum (u32) of everything before it
+const MESSAGE: u8 = 1;
+const ACK: u8 = 2;
+const HEADER_LEN: usize = 1 + 8 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+enum Record<'a> {
+    Message { id: u64, payload: &'a [u8] },
+    Ack { id: u64 },
+}
+
+struct Pending<T> {
+    id: u64,
+    segment: u64,
+    message: T,
+}
+
+struct State<T> {
+    pending: VecDeque<Pending<T>>,
+    // Delivered but not acknowledged yet, id to the segment the message was written to
+    in_flight: HashMap<u64, u64>,
+    // Every segment on disk, with the number of its messages that have not been acknowledged
+    segments: BTreeMap<u64, usize>,
+    current: File,
+    current_segment: u64,
+    current_size: u64,
+    next_id: u64,
+    // Tests set this to make the next write stop after this many bytes and fail
+    #[cfg(test)]
+    short_write: Option<usize>,
+}
+
+/**
+ * section_4/channel_vec_dequeue.rs keeps its messages in memory, so they are lost if the process exits (or crashes) before they are received. DurableQueue has the same send()/receive() shape, and also appends every message to a segment file in a directory, so a restarted process can pick up where it left off.
+ *
+ * The files are append only:
+ * - send() appends a MESSAGE record with the message's id and its serialized bytes
+ * - receive() hands out the message with its id, and the receiver calls ack(id) once it has been dealt with, which appends an ACK record
+ * - open() replays every segment in order, and any message without an ACK (never received, or received but not acknowledged before the crash) is queued again
+ *
+ * Every record ends with a checksum. A crash part way through a write leaves a torn record at the end of a segment, recovery stops at the first record in the newest segment that does not check out and truncates the file there, so new records are appended after the last good one. Older segments were complete before the next one was started, so a bad record in one of them is reported as corruption rather than cut off. A write that fails without a crash is truncated straight away in the same way, so it never ends up in front of later records.
+ *
+ * Once a segment grows past Options::segment_size, a new one is started. Segments are deleted oldest first once every message in them has been acknowledged. An ACK can be in a later segment than its message, but never an earlier one, so deleting from the oldest end never loses an ACK for a message that is still on disk.
+ *
+ * The in memory queue and the open segment file are guarded by one Mutex, with a Condvar to block receive() whilst the queue is empty, same as channel_vec_dequeue.rs.
+ */
+pub struct DurableQueue<T> {
+    dir: PathBuf,
+    options: Options,
+    state: Mutex<State<T>>,
+    item_ready: Condvar,
+    // Copy of the queue length, written whilst holding the lock so len() can read it without locking
+    len: AtomicUsize,
+}
+
+impl<T: Serialize> DurableQueue<T> {
+    ///
+    /// Opens (or creates) the queue in dir, recovering every message that was not acknowledged
+    ///
+    pub fn open(dir: impl AsRef<Path>, options: Options) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let state = recover(&dir)?;
+
+        let queue = Self {
+            dir,
+            options,
+            len: AtomicUsize::new(state.pending.len()),
+            state: Mutex::new(state),
+            item_ready: Condvar::new(),
+        };
+        let mut state = queue.state.lock().unwrap();
+        queue.remove_acknowledged_segments(&mut state)?;
+        drop(state);
+        Ok(queue)
+    }
+
+    ///
+    /// Writes the message to disk, then queues it. Returns the id it will be delivered with.
+    ///
+    pub fn send(&self, message: T) -> io::Result<u64> {
+        let mut payload = Vec::new();
+        message.serialize(&mut payload);
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        self.append(&mut state, MESSAGE, id, &payload)?;
+        state.next_id += 1;
+
+        let segment = state.current_segment;
+        *state.segments.get_mut(&segment).unwrap() += 1;
+        state.pending.push_back(Pending {
+            id,
+            segment,
+            message,
+        });
+        self.len.store(state.pending.len(), Ordering::Relaxed);
+        drop(state);
+
+        self.item_ready.notify_one();
+        Ok(id)
+    }
+
+    pub fn try_receive(&self) -> Option<Delivery<T>> {
+        let mut state = self.state.lock().unwrap();
+        self.deliver(&mut state)
+    }
+
+    ///
+    /// Blocks till there is a message. It stays on disk, and is delivered again after a restart, till it is acknowledged.
+    ///
+    pub fn receive(&self) -> Delivery<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(delivery) = self.deliver(&mut state) {
+                return delivery;
+            }
+            state = self.item_ready.wait(state).unwrap();
+        }
+    }
+
+    ///
+    /// Marks a delivered message as handled, so it is not delivered again after a restart
+    ///
+    pub fn ack(&self, id: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(segment) = state.in_flight.remove(&id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("message {id} is not waiting for an ack"),
+            ));
+        };
+
+        if let Err(error) = self.append(&mut state, ACK, id, &[]) {
+            state.in_flight.insert(id, segment);
+            return Err(error);
+        }
+        *state.segments.get_mut(&segment).unwrap() -= 1;
+        self.remove_acknowledged_segments(&mut state)
+    }
+
+    ///
+    /// Messages waiting to be received, not counting ones that have been delivered but not acknowledged
+    ///
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn deliver(&self, state: &mut MutexGuard<'_, State<T>>) -> Option<Delivery<T>> {
+        let pending = state.pending.pop_front()?;
+        state.in_flight.insert(pending.id, pending.segment);
+        self.len.store(state.pending.len(), Ordering::Relaxed);
+        Some(Delivery {
+            id: pending.id,
+            message: pending.message,
+        })
+    }
+
+    fn append(&self, state: &mut State<T>, kind: u8, id: u64, payload: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+        encode(kind, id, payload, &mut record);
+
+        if state.current_size > 0
+            && state.current_size + record.len() as u64 > self.options.segment_size
+        {
+            let segment = state.current_segment + 1;
+            state.current = create_segment(&self.dir, segment)?;
+            state.current_segment = segment;
+            state.current_size = 0;
+            state.segments.insert(segment, 0);
+        }
+
+        if let Err(error) = self.write_record(state, &record) {
+            // Part of the record may be in the file. Later records would go after it, and recover() stops at the first bad record, so cut it off before returning. The file is opened for appending, so the next write goes to the new end.
+            state.current.set_len(state.current_size)?;
+            return Err(error);
+        }
+        state.current_size += record.len() as u64;
+        Ok(())
+    }
+
+    fn write_record(&self, state: &mut State<T>, record: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(len) = state.short_write.take() {
+            state.current.write_all(&record[..len])?;
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "short write"));
+        }
+
+        state.current.write_all(record)?;
+        if self.options.sync {
+            state.current.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn remove_acknowledged_segments(&self, state: &mut State<T>) -> io::Result<()> {
+        while let Some((&segment, &unacked)) = state.segments.first_key_value() {
+            if unacked > 0 || segment == state.current_segment {
+                break;
+            }
+            fs::remove_file(segment_path(&self.dir, segment))?;
+            state.segments.remove(&segment);
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{segment:020}.segment"))
+}
+
+fn create_segment(dir: &Path, segment: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, segment))
+}
+
+// FNV-1a, enough to spot a torn or zero filled record
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn encode(kind: u8, id: u64, payload: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    out.push(kind);
+    out.extend_from_slice(&id.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    let sum = checksum(&out[start..]);
+    out.extend_from_slice(&sum.to_le_bytes());
+}
+
+///
+/// The record at the start of bytes and its length, None if it is incomplete or fails the checksum
+///
+fn decode(bytes: &[u8]) -> Option<(Record<'_>, usize)> {
+    let header = bytes.get(..HEADER_LEN)?;
+    let kind = header[0];
+    let id = u64::from_le_bytes(header[1..9].try_into().unwrap());
+    let payload_len = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+
+    let body_len = HEADER_LEN.checked_add(payload_len)?;
+    let sum = bytes.get(body_len..body_len.checked_add(CHECKSUM_LEN)?)?;
+    if u32::from_le_bytes(sum.try_into().unwrap()) != checksum(&bytes[..body_len]) {
+        return None;
+    }
+
+    let record = match kind {
+        MESSAGE => Record::Message {
+            id,
+            payload: &bytes[HEADER_LEN..body_len],
+        },
+        ACK => Record::Ack { id },
+        _ => return None,
+    };
+    Some((record, body_len + CHECKSUM_LEN))
+}
+
+///
+/// Replays every segment in dir, truncating any torn records, and rebuilds the queue of unacknowledged messages
+///
+fn recover<T: Serialize>(dir: &Path) -> io::Result<State<T>> {
+    let mut segments = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let index = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".segment"))
+            .and_then(|index| index.parse::<u64>().ok());
+        if let Some(index) = index {
+            segments.insert(index, 0);
+        }
+    }
+
+    let mut messages = BTreeMap::new();
+    let mut acked = HashSet::new();
+    let mut next_id = 0;
+    let mut current_size = 0;
+    let newest = segments.keys().next_back().copied();
+
+    for &segment in segments.keys() {
+        let path = segment_path(dir, segment);
+        let bytes = fs::read(&path)?;
+
+        let mut offset = 0;
+        while let Some((record, len)) = decode(&bytes[offset..]) {
+            let id = match record {
+                Record::Message { id, payload } => {
+                    let message = T::deserialize(payload).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("message {id} in {} cannot be deserialized", path.display()),
+                        )
+                    })?;
+                    messages.insert(id, (segment, message));
+                    id
+                }
+                Record::Ack { id } => {
+                    acked.insert(id);
+                    id
+                }
+            };
+            next_id = next_id.max(id + 1);
+            offset += len;
+        }
+
+        if offset < bytes.len() {
+            // Only the newest segment can have been torn by a crash, a bad record anywhere else is corruption
+            if Some(segment) != newest {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupt record at byte {offset} of {}", path.display()),
+                ));
+            }
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset as u64)?;
+        }
+        current_size = offset as u64;
+    }
+
+    let mut pending = VecDeque::new();
+    for (id, (segment, message)) in messages {
+        if !acked.contains(&id) {
+            *segments.get_mut(&segment).unwrap() += 1;
+            pending.push_back(Pending {
+                id,
+                segment,
+                message,
+            });
+        }
+    }
+
+    let current_segment = match segments.last_key_value() {
+        Some((&segment, _)) => segment,
+        None => {
+            segments.insert(0, 0);
+            current_size = 0;
+            0
+        }
+    };
+
+    Ok(State {
+        pending,
+        in_flight: HashMap::new(),
+        segments,
+        current: create_segment(dir, current_segment)?,
+        current_segment,
+        current_size,
+        next_id,
+        #[cfg(test)]
+        short_write: None,
+    })
+}
+
+pub fn durable_queue_main() {
+    let dir = env::temp_dir().join("rust-atomics-durable-queue-main");
+    let _ = fs::remove_dir_all(&dir);
+
+    {
+        let queue = DurableQueue::<String>::open(&dir, Options::default()).unwrap();
+        for i in 0..3 {
+            queue.send(format!("job {i}")).unwrap();
+        }
+
+        let delivery = queue.receive();
+        println!("handled {:?}", delivery.message);
+        queue.ack(delivery.id).unwrap();
+
+        let delivery = queue.receive();
+        println!("crashed whilst handling {:?}", delivery.message);
+        // The queue is dropped without the ack, as if the process had died
+    }
+
+    let queue = DurableQueue::<String>::open(&dir, Options::default()).unwrap();
+    println!("recovered {} jobs", queue.len());
+    while let Some(delivery) = queue.try_receive() {
+        println!("handled {:?}", delivery.message);
+        queue.ack(delivery.id).unwrap();
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{segment_path, Delivery, DurableQueue, Options};
+    use std::{
+        env, fs,
+        io::{self, Write},
+        path::PathBuf,
+        process,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rust-atomics-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn segment_count(dir: &PathBuf) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_delivered_again_after_reopening() {
+        let dir = temp_dir("redeliver");
+        {
+            let queue = DurableQueue::open(&dir, Options::default()).unwrap();
+            for i in 0..4 {
+                assert_eq!(queue.send(i * 10).unwrap(), i);
+            }
+            let first = queue.receive();
+            queue.ack(first.id).unwrap();
+            // Received, but not acknowledged
+            queue.receive();
+
+            assert_eq!(
+                queue.ack(first.id).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+
+        let queue = DurableQueue::<u64>::open(&dir, Options::default()).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.receive(), Delivery { id: 1, message: 10 });
+        assert_eq!(queue.try_receive().map(|d| d.message), Some(20));
+        assert_eq!(queue.try_receive().map(|d| d.message), Some(30));
+        assert_eq!(queue.try_receive(), None);
+        // New ids carry on after the recovered ones
+        assert_eq!(queue.send(40).unwrap(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn segments_rotate_and_are_deleted_once_acknowledged() {
+        let dir = temp_dir("rotate");
+        let options = Options {
+            segment_size: 64,
+            sync: false,
+        };
+        let queue = DurableQueue::open(&dir, options).unwrap();
+
+        for i in 0..10 {
+            queue.send(format!("message {i}")).unwrap();
+        }
+        assert!(segment_count(&dir) > 3);
+
+        while let Some(delivery) = queue.try_receive() {
+            queue.ack(delivery.id).unwrap();
+        }
+        // Only the segment still being written to is left
+        assert_eq!(segment_count(&dir), 1);
+
+        drop(queue);
+        let queue = DurableQueue::<String>::open(&dir, options).unwrap();
+        assert!(queue.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_record_is_dropped_and_the_file_truncated() {
+        let dir = temp_dir("torn");
+        {
+            let queue = DurableQueue::open(&dir, Options::default()).unwrap();
+            queue.send(b"complete".to_vec()).unwrap();
+        }
+
+        // A crash part way through the next write
+        let path = segment_path(&dir, 0);
+        let good_len = fs::metadata(&path).unwrap().len();
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 1, 0, 0, 0, 0, 0, 0, 0, 9]).unwrap();
+        drop(file);
+
+        let queue = DurableQueue::<Vec<u8>>::open(&dir, Options::default()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+        queue.send(b"after".to_vec()).unwrap();
+        drop(queue);
+
+        let queue = DurableQueue::<Vec<u8>>::open(&dir, Options::default()).unwrap();
+        assert_eq!(queue.receive().message, b"complete");
+        assert_eq!(queue.receive().message, b"after");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_bad_record_in_an_older_segment_is_an_error() {
+        let dir = temp_dir("corrupt");
+        let options = Options {
+            segment_size: 64,
+            sync: false,
+        };
+        {
+            let queue = DurableQueue::open(&dir, options).unwrap();
+            for i in 0..8u64 {
+                queue.send(i).unwrap();
+            }
+        }
+        assert!(segment_count(&dir) > 1);
+
+        let path = segment_path(&dir, 0);
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let error = DurableQueue::<u64>::open(&dir, options).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // Nothing was cut off
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_does_not_hide_later_records() {
+        let dir = temp_dir("short-write");
+        {
+            let queue = DurableQueue::open(&dir, Options::default()).unwrap();
+            queue.send(1u64).unwrap();
+
+            queue.state.lock().unwrap().short_write = Some(5);
+            assert!(queue.send(2).is_err());
+            assert_eq!(queue.len(), 1);
+
+            queue.send(3).unwrap();
+            let delivery = queue.receive();
+            queue.ack(delivery.id).unwrap();
+        }
+
+        let queue = DurableQueue::<u64>::open(&dir, Options::default()).unwrap();
+        // The ack and the message sent after the failure both survived
+        assert_eq!(queue.try_receive().map(|d| d.message), Some(3));
+        assert_eq!(queue.try_receive(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,18 +1,70 @@
+mod atomic_waker;
+mod block_on;
+mod cancel;
 mod channel_avoid_borrowing;
 mod channel_blocking;
+mod channel_broadcast;
+mod channel_deadline;
+mod channel_linked_blocks;
+mod channel_mpsc_linked;
 mod channel_one_shot;
+mod channel_priority;
+mod channel_rendezvous;
+mod channel_ring_buffer;
 mod channel_sender_receiver;
 mod channel_vec_dequeue;
+mod channel_watch;
+mod disruptor;
+mod durable_queue;
+mod one_shot;
+mod receiver_iter;
+mod rpc;
+mod select;
+#[cfg(target_os = "linux")]
+mod shared_memory_spsc;
 mod spin_lock;
+mod spsc_ring_buffer;
 
+pub use atomic_waker::*;
+pub use block_on::*;
+pub use cancel::*;
 #[allow(ambiguous_glob_reexports, unused)]
 pub use channel_avoid_borrowing::*;
 #[allow(ambiguous_glob_reexports, unused)]
 pub use channel_blocking::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_broadcast::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_deadline::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_linked_blocks::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_mpsc_linked::*;
 #[allow(ambiguous_glob_reexports)]
 pub use channel_one_shot::*;
 #[allow(ambiguous_glob_reexports, unused)]
+pub use channel_priority::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_rendezvous::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_ring_buffer::*;
+#[allow(ambiguous_glob_reexports, unused)]
 pub use channel_sender_receiver::*;
 #[allow(ambiguous_glob_reexports, unused)]
 pub use channel_vec_dequeue::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use channel_watch::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use disruptor::*;
+pub use durable_queue::*;
+pub use one_shot::*;
+pub use receiver_iter::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use rpc::*;
+pub use select::*;
+#[cfg(target_os = "linux")]
+#[allow(ambiguous_glob_reexports, unused)]
+pub use shared_memory_spsc::*;
 pub use spin_lock::*;
+#[allow(ambiguous_glob_reexports, unused)]
+pub use spsc_ring_buffer::*;
@@ -0,0 +1,320 @@
+use std::thread;
+
+use super::{channel_avoid_borrowing, channel_blocking, channel_one_shot, channel_sender_receiver};
+
+/**
+ * OneShot covers the four oneshot channels (channel_one_shot, channel_sender_receiver, channel_avoid_borrowing and channel_blocking), with a marker type per channel and the ends as generic associated types, as some of them borrow the Channel.
+ */
+pub trait OneShot {
+    type Channel<T>;
+    type Sender<'a, T: Send + 'a>: Send;
+    type Receiver<'a, T: Send + 'a>;
+
+    ///
+    /// True if receive() parks till a message arrives, false if it panics when nothing has been sent
+    ///
+    const RECEIVE_BLOCKS: bool;
+
+    fn new<T>() -> Self::Channel<T>;
+
+    fn split<'a, T: Send + 'a>(
+        channel: &'a mut Self::Channel<T>,
+    ) -> (Self::Sender<'a, T>, Self::Receiver<'a, T>);
+
+    fn send<'a, T: Send + 'a>(sender: Self::Sender<'a, T>, message: T);
+
+    fn is_ready<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> bool;
+
+    fn receive<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> T;
+}
+
+pub struct OneOff;
+pub struct SenderReceiver;
+pub struct AvoidBorrowing;
+pub struct Blocking;
+
+impl OneShot for OneOff {
+    type Channel<T> = channel_one_shot::Channel<T>;
+    type Sender<'a, T: Send + 'a> = &'a channel_one_shot::Channel<T>;
+    type Receiver<'a, T: Send + 'a> = &'a channel_one_shot::Channel<T>;
+
+    const RECEIVE_BLOCKS: bool = false;
+
+    fn new<T>() -> Self::Channel<T> {
+        channel_one_shot::Channel::new()
+    }
+
+    fn split<'a, T: Send + 'a>(
+        channel: &'a mut Self::Channel<T>,
+    ) -> (Self::Sender<'a, T>, Self::Receiver<'a, T>) {
+        *channel = channel_one_shot::Channel::new();
+        (channel, channel)
+    }
+
+    fn send<'a, T: Send + 'a>(sender: Self::Sender<'a, T>, message: T) {
+        sender.send(message);
+    }
+
+    fn is_ready<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> bool {
+        receiver.is_ready()
+    }
+
+    fn receive<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> T {
+        receiver.receive()
+    }
+}
+
+impl OneShot for SenderReceiver {
+    type Channel<T> = ();
+    type Sender<'a, T: Send + 'a> = channel_sender_receiver::Sender<T>;
+    type Receiver<'a, T: Send + 'a> = channel_sender_receiver::Receiver<T>;
+
+    const RECEIVE_BLOCKS: bool = false;
+
+    fn new<T>() -> Self::Channel<T> {}
+
+    fn split<'a, T: Send + 'a>(
+        _channel: &'a mut Self::Channel<T>,
+    ) -> (Self::Sender<'a, T>, Self::Receiver<'a, T>) {
+        channel_sender_receiver::channel()
+    }
+
+    fn send<'a, T: Send + 'a>(sender: Self::Sender<'a, T>, message: T) {
+        sender.send(message);
+    }
+
+    fn is_ready<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> bool {
+        receiver.is_ready()
+    }
+
+    fn receive<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> T {
+        receiver.receive()
+    }
+}
+
+impl OneShot for AvoidBorrowing {
+    type Channel<T> = channel_avoid_borrowing::Channel<T>;
+    type Sender<'a, T: Send + 'a> = channel_avoid_borrowing::Sender<'a, T>;
+    type Receiver<'a, T: Send + 'a> = channel_avoid_borrowing::Receiver<'a, T>;
+
+    const RECEIVE_BLOCKS: bool = false;
+
+    fn new<T>() -> Self::Channel<T> {
+        channel_avoid_borrowing::Channel::new()
+    }
+
+    fn split<'a, T: Send + 'a>(
+        channel: &'a mut Self::Channel<T>,
+    ) -> (Self::Sender<'a, T>, Self::Receiver<'a, T>) {
+        channel.split()
+    }
+
+    fn send<'a, T: Send + 'a>(sender: Self::Sender<'a, T>, message: T) {
+        sender.send(message);
+    }
+
+    fn is_ready<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> bool {
+        receiver.is_ready()
+    }
+
+    fn receive<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> T {
+        receiver.receive()
+    }
+}
+
+impl OneShot for Blocking {
+    type Channel<T> = channel_blocking::Channel<T>;
+    type Sender<'a, T: Send + 'a> = channel_blocking::Sender<'a, T>;
+    type Receiver<'a, T: Send + 'a> = channel_blocking::Receiver<'a, T>;
+
+    const RECEIVE_BLOCKS: bool = true;
+
+    fn new<T>() -> Self::Channel<T> {
+        channel_blocking::Channel::new()
+    }
+
+    fn split<'a, T: Send + 'a>(
+        channel: &'a mut Self::Channel<T>,
+    ) -> (Self::Sender<'a, T>, Self::Receiver<'a, T>) {
+        channel.split()
+    }
+
+    fn send<'a, T: Send + 'a>(sender: Self::Sender<'a, T>, message: T) {
+        sender.send(message);
+    }
+
+    fn is_ready<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> bool {
+        receiver.is_ready()
+    }
+
+    fn receive<'a, T: Send + 'a>(receiver: &Self::Receiver<'a, T>) -> T {
+        receiver.receive()
+    }
+}
+
+///
+/// Waits for the message however the channel supports it, parking in receive() or yielding till is_ready()
+///
+fn wait_and_receive<'a, C: OneShot, T: Send + 'a>(receiver: &C::Receiver<'a, T>) -> T {
+    if !C::RECEIVE_BLOCKS {
+        while !C::is_ready(receiver) {
+            thread::yield_now();
+        }
+    }
+    C::receive(receiver)
+}
+
+///
+/// Sends the message from another thread and waits for it, the same code for every channel
+///
+fn hand_off<C: OneShot, T: Send>(message: T) -> T {
+    let mut channel = C::new::<T>();
+    thread::scope(|s| {
+        let (sender, receiver) = C::split(&mut channel);
+        s.spawn(move || C::send(sender, message));
+        wait_and_receive::<C, T>(&receiver)
+    })
+}
+
+pub fn one_shot_main() {
+    println!("one off: {}", hand_off::<OneOff, _>("hello"));
+    println!(
+        "sender receiver: {}",
+        hand_off::<SenderReceiver, _>("hello")
+    );
+    println!(
+        "avoid borrowing: {}",
+        hand_off::<AvoidBorrowing, _>("hello")
+    );
+    println!("blocking: {}", hand_off::<Blocking, _>("hello"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hand_off, wait_and_receive, OneShot};
+    use crate::test_util::DetectDrop;
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    fn send_then_receive<C: OneShot>() {
+        let mut channel = C::new();
+        let (sender, receiver) = C::split(&mut channel);
+        assert!(!C::is_ready(&receiver));
+
+        C::send(sender, String::from("hello world!"));
+        assert!(C::is_ready(&receiver));
+        assert_eq!(C::receive(&receiver), "hello world!");
+    }
+
+    fn receive_before_send<C: OneShot>() {
+        let mut channel = C::new();
+        thread::scope(|s| {
+            let (sender, receiver) = C::split(&mut channel);
+
+            if C::RECEIVE_BLOCKS {
+                s.spawn(move || {
+                    thread::sleep(Duration::from_millis(20));
+                    C::send(sender, 7);
+                });
+                assert_eq!(C::receive(&receiver), 7);
+            } else {
+                let early = panic::catch_unwind(AssertUnwindSafe(|| C::receive(&receiver)));
+                assert!(early.is_err(), "receive() with nothing sent should panic");
+
+                C::send(sender, 7);
+                assert_eq!(C::receive(&receiver), 7);
+            }
+        });
+    }
+
+    fn unreceived_message_is_dropped_once<C: OneShot>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+
+        let mut channel = C::new();
+        let (sender, receiver) = C::split(&mut channel);
+        C::send(sender, DetectDrop(num_drops.clone()));
+        drop(receiver);
+        drop(channel);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+
+        // Received messages belong to the caller, the channel must not drop them again
+        let mut channel = C::new();
+        let (sender, receiver) = C::split(&mut channel);
+        C::send(sender, DetectDrop(num_drops.clone()));
+        drop(C::receive(&receiver));
+        drop(receiver);
+        drop(channel);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 2);
+
+        // Nothing sent, nothing to drop
+        let mut channel = C::new::<DetectDrop>();
+        drop(C::split(&mut channel));
+        drop(channel);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 2);
+    }
+
+    fn cross_thread_hand_off<C: OneShot>() {
+        let message: Vec<u64> = (0..1000).collect();
+        assert_eq!(hand_off::<C, _>(message.clone()), message);
+    }
+
+    fn stress<C: OneShot>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        for i in 0..1000 {
+            let mut channel = C::new();
+            thread::scope(|s| {
+                let (sender, receiver) = C::split(&mut channel);
+                let message = (i, DetectDrop(num_drops.clone()));
+                s.spawn(move || C::send(sender, message));
+
+                let (received, _detect_drop) = wait_and_receive::<C, _>(&receiver);
+                assert_eq!(received, i);
+            });
+        }
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1000);
+    }
+
+    // The same tests for every channel
+    macro_rules! one_shot_suite {
+        ($name:ident, $channel:ident) => {
+            mod $name {
+                #[test]
+                fn send_then_receive() {
+                    super::send_then_receive::<super::super::$channel>();
+                }
+
+                #[test]
+                fn receive_before_send() {
+                    super::receive_before_send::<super::super::$channel>();
+                }
+
+                #[test]
+                fn unreceived_message_is_dropped_once() {
+                    super::unreceived_message_is_dropped_once::<super::super::$channel>();
+                }
+
+                #[test]
+                fn cross_thread_hand_off() {
+                    super::cross_thread_hand_off::<super::super::$channel>();
+                }
+
+                #[test]
+                fn stress() {
+                    super::stress::<super::super::$channel>();
+                }
+            }
+        };
+    }
+
+    one_shot_suite!(one_off, OneOff);
+    one_shot_suite!(sender_receiver, SenderReceiver);
+    one_shot_suite!(avoid_borrowing, AvoidBorrowing);
+    one_shot_suite!(blocking, Blocking);
+}
@@ -0,0 +1,75 @@
+/**
+ * Consuming a channel by hand means writing loop { receive() } like section_1/thread_condvar.rs, and remembering to break once the senders have gone.
+ *
+ * Every multi-message Receiver that knows when its senders are dropped implements Receive, and gets iterators from the shared types here:
+ * - Iter, from receiver.iter() or `for message in &receiver`, blocks for each message and ends once every Sender is dropped and the queue is drained
+ * - TryIter, from receiver.try_iter(), drains whatever is queued right now and never blocks
+ * - IntoIter, from `for message in receiver`, same as Iter but owns the Receiver
+ */
+pub trait Receive {
+    type Message;
+
+    ///
+    /// Blocks till a message is available, None once every Sender has been dropped and the queue is empty
+    ///
+    fn receive(&self) -> Option<Self::Message>;
+
+    ///
+    /// None if there is nothing to receive right now
+    ///
+    fn try_receive(&self) -> Option<Self::Message>;
+}
+
+pub struct Iter<'a, R: Receive> {
+    receiver: &'a R,
+}
+
+pub struct TryIter<'a, R: Receive> {
+    receiver: &'a R,
+}
+
+pub struct IntoIter<R: Receive> {
+    receiver: R,
+}
+
+impl<'a, R: Receive> Iter<'a, R> {
+    pub fn new(receiver: &'a R) -> Self {
+        Iter { receiver }
+    }
+}
+
+impl<'a, R: Receive> TryIter<'a, R> {
+    pub fn new(receiver: &'a R) -> Self {
+        TryIter { receiver }
+    }
+}
+
+impl<R: Receive> IntoIter<R> {
+    pub fn new(receiver: R) -> Self {
+        IntoIter { receiver }
+    }
+}
+
+impl<R: Receive> Iterator for Iter<'_, R> {
+    type Item = R::Message;
+
+    fn next(&mut self) -> Option<R::Message> {
+        self.receiver.receive()
+    }
+}
+
+impl<R: Receive> Iterator for TryIter<'_, R> {
+    type Item = R::Message;
+
+    fn next(&mut self) -> Option<R::Message> {
+        self.receiver.try_receive()
+    }
+}
+
+impl<R: Receive> Iterator for IntoIter<R> {
+    type Item = R::Message;
+
+    fn next(&mut self) -> Option<R::Message> {
+        self.receiver.receive()
+    }
+}
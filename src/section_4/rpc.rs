@@ -0,0 +1,304 @@
+use std::{
+    sync::{
+        atomic::{self, AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use super::{channel_mpsc_linked, channel_sender_receiver, IntoIter, Iter, Receive};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// The server went away, or dropped the Responder, without replying.
+    Disconnected,
+    /// No reply arrived in time. The server may still handle the request, the reply is then dropped.
+    Timeout,
+}
+
+// Shared between one call and its Responder
+struct CallState {
+    client: Thread,
+    // Set by the server once it has handed the request out, so the client knows a reply may still come after the server is dropped
+    taken: AtomicBool,
+    // Set if the Responder is dropped without replying
+    dropped: AtomicBool,
+}
+
+/**
+ * Sending a request and waiting for its answer means building a request type that carries its own reply channel each time. Client and Server package that up:
+ * - call() creates a oneshot from section_4/channel_sender_receiver.rs, sends the request with the oneshot's Sender (wrapped in a Responder) over section_4/channel_mpsc_linked.rs, and parks till the reply is ready
+ * - the Server is iterated for (request, Responder) pairs, and respond() sends the reply and unparks the calling thread
+ *
+ * Clients can be cloned and shared between threads, there is a single Server.
+ *
+ * A call must not wait forever for a reply that will never come:
+ * - dropping a Responder without replying flags the call and unparks the client
+ * - dropping the Server marks it closed, then drops every request still queued (and so their Responders)
+ *
+ * A request sent just as the Server is being dropped can miss the drain. The client's send is a SeqCst swap followed by a SeqCst load of closed, and the Server stores closed then has a SeqCst fence before draining, so either the drain sees the request or the client sees closed (same reasoning as section_3/seqcst_ordering.rs). A closed server only counts as disconnected for requests it has not taken, a request that was handed to a worker can still be answered.
+ */
+pub struct Client<Req, Resp> {
+    requests: channel_mpsc_linked::Sender<(Req, Responder<Resp>)>,
+    server_closed: Arc<AtomicBool>,
+}
+
+pub struct Server<Req, Resp> {
+    requests: channel_mpsc_linked::Receiver<(Req, Responder<Resp>)>,
+    closed: Arc<AtomicBool>,
+}
+
+pub struct Responder<Resp> {
+    sender: Option<channel_sender_receiver::Sender<Resp>>,
+    state: Arc<CallState>,
+}
+
+pub fn rpc<Req, Resp>() -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let (sender, receiver) = channel_mpsc_linked::channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    (
+        Client {
+            requests: sender,
+            server_closed: closed.clone(),
+        },
+        Server {
+            requests: receiver,
+            closed,
+        },
+    )
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    ///
+    /// Blocks till the server replies
+    ///
+    pub fn call(&self, request: Req) -> Result<Resp, CallError> {
+        self.call_deadline(request, None)
+    }
+
+    pub fn call_timeout(&self, request: Req, timeout: Duration) -> Result<Resp, CallError> {
+        self.call_deadline(request, Some(Instant::now() + timeout))
+    }
+
+    fn call_deadline(&self, request: Req, deadline: Option<Instant>) -> Result<Resp, CallError> {
+        if self.server_closed.load(Ordering::SeqCst) {
+            return Err(CallError::Disconnected);
+        }
+
+        let (sender, receiver) = channel_sender_receiver::channel();
+        let state = Arc::new(CallState {
+            client: thread::current(),
+            taken: AtomicBool::new(false),
+            dropped: AtomicBool::new(false),
+        });
+        let responder = Responder {
+            sender: Some(sender),
+            state: state.clone(),
+        };
+        self.requests.send((request, responder));
+
+        loop {
+            if receiver.is_ready() {
+                return Ok(receiver.receive());
+            }
+            if state.dropped.load(Ordering::Acquire)
+                || (self.server_closed.load(Ordering::SeqCst)
+                    && !state.taken.load(Ordering::SeqCst))
+            {
+                return Err(CallError::Disconnected);
+            }
+
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(CallError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+    }
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Self {
+        Client {
+            requests: self.requests.clone(),
+            server_closed: self.server_closed.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Server<Req, Resp> {
+    fn take(&self, request: Option<(Req, Responder<Resp>)>) -> Option<(Req, Responder<Resp>)> {
+        let (request, responder) = request?;
+        responder.state.taken.store(true, Ordering::SeqCst);
+        Some((request, responder))
+    }
+
+    ///
+    /// Blocks till a request arrives, None once every Client has been dropped
+    ///
+    pub fn receive(&self) -> Option<(Req, Responder<Resp>)> {
+        self.take(self.requests.receive())
+    }
+
+    #[allow(unused)]
+    pub fn try_receive(&self) -> Option<(Req, Responder<Resp>)> {
+        self.take(self.requests.try_receive())
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> Iter<'_, Self> {
+        Iter::new(self)
+    }
+}
+
+impl<Req, Resp> Drop for Server<Req, Resp> {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // Pairs with the client's SeqCst send and load of closed, so a request sent after this point is either drained here or sees closed
+        atomic::fence(Ordering::SeqCst);
+        // Dropping the queued Responders tells their clients
+        while self.requests.try_receive().is_some() {}
+    }
+}
+
+impl<Req, Resp> Receive for Server<Req, Resp> {
+    type Message = (Req, Responder<Resp>);
+
+    fn receive(&self) -> Option<Self::Message> {
+        self.receive()
+    }
+
+    fn try_receive(&self) -> Option<Self::Message> {
+        self.try_receive()
+    }
+}
+
+impl<Req, Resp> IntoIterator for Server<Req, Resp> {
+    type Item = (Req, Responder<Resp>);
+    type IntoIter = IntoIter<Self>;
+
+    fn into_iter(self) -> IntoIter<Self> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'a, Req, Resp> IntoIterator for &'a Server<Req, Resp> {
+    type Item = (Req, Responder<Resp>);
+    type IntoIter = Iter<'a, Server<Req, Resp>>;
+
+    fn into_iter(self) -> Iter<'a, Server<Req, Resp>> {
+        Iter::new(self)
+    }
+}
+
+impl<Resp> Responder<Resp> {
+    ///
+    /// Sends the reply and wakes the client
+    ///
+    pub fn respond(mut self, response: Resp) {
+        self.sender.take().unwrap().send(response);
+        self.state.client.unpark();
+    }
+}
+
+impl<Resp> Drop for Responder<Resp> {
+    fn drop(&mut self) {
+        if self.sender.is_some() {
+            self.state.dropped.store(true, Ordering::Release);
+            self.state.client.unpark();
+        }
+    }
+}
+
+pub fn rpc_main() {
+    let (client, server) = rpc::<u64, u64>();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            for (n, responder) in server {
+                println!("server squaring {n}");
+                responder.respond(n * n);
+            }
+            println!("every client has gone, server stopping");
+        });
+
+        for id in 0..3 {
+            let client = client.clone();
+            s.spawn(move || {
+                let reply = client.call(id + 2);
+                println!("client {id} got {:?}", reply);
+            });
+        }
+        drop(client);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rpc, CallError};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn every_call_gets_its_own_reply() {
+        let (client, server) = rpc();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for (request, responder) in server {
+                    responder.respond(format!("{request}!"));
+                }
+            });
+
+            for i in 0..4 {
+                let client = client.clone();
+                s.spawn(move || {
+                    for j in 0..25 {
+                        let request = i * 100 + j;
+                        assert_eq!(client.call(request), Ok(format!("{request}!")));
+                    }
+                });
+            }
+            drop(client);
+        });
+    }
+
+    #[test]
+    fn call_times_out_without_a_reply() {
+        let (client, server) = rpc::<i32, i32>();
+
+        let t = thread::spawn(move || {
+            let (request, responder) = server.receive().unwrap();
+            thread::sleep(Duration::from_millis(100));
+            // The client has given up by now, the reply is dropped
+            responder.respond(request);
+        });
+
+        assert_eq!(
+            client.call_timeout(1, Duration::from_millis(20)),
+            Err(CallError::Timeout)
+        );
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_server_or_responder_disconnects_the_call() {
+        let (client, server) = rpc::<i32, i32>();
+
+        let t = thread::spawn(move || {
+            // Drop the Responder without replying
+            let (_, responder) = server.receive().unwrap();
+            drop(responder);
+            server
+        });
+        assert_eq!(client.call(1), Err(CallError::Disconnected));
+
+        drop(t.join().unwrap());
+        assert_eq!(client.call(2), Err(CallError::Disconnected));
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use super::{
+    channel_avoid_borrowing, channel_blocking, channel_broadcast, channel_deadline,
+    channel_linked_blocks, channel_mpsc_linked, channel_one_shot, channel_priority,
+    channel_rendezvous, channel_ring_buffer, channel_sender_receiver, channel_vec_dequeue,
+    channel_watch, spsc_ring_buffer,
+};
+
+///
+/// Anything that can report if a receive would return without blocking
+///
+pub trait Selectable {
+    fn is_ready(&self) -> bool;
+}
+
+impl<T> Selectable for channel_one_shot::Channel<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_sender_receiver::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_avoid_borrowing::Receiver<'_, T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_blocking::Receiver<'_, T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_vec_dequeue::Channel<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_ring_buffer::Channel<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_vec_dequeue::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_ring_buffer::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_mpsc_linked::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for spsc_ring_buffer::Consumer<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T: Clone> Selectable for channel_broadcast::Subscriber<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_watch::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_rendezvous::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<P: Ord, T> Selectable for channel_priority::Receiver<P, T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_deadline::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+impl<T> Selectable for channel_linked_blocks::Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.is_ready()
+    }
+}
+
+/**
+ * Waits on a set of receivers at once (e.g. a work channel and a shutdown channel), and reports the index of the one that is ready.
+ *
+ * - select() blocks till one of the receivers is ready
+ * - select_timeout() is the timeout arm, None if the time elapsed first
+ * - try_select() is the default arm, None straight away if nothing is ready
+ *
+ * Select only tells us which receiver is ready, the caller then does the receive on it. Another consumer of the same channel may have taken the message in between, so for multi-consumer channels use the non-blocking receive after a select.
+ *
+ * The receivers do not know about the selecting thread, so we cannot be woken by every sender. Instead the thread is parked with park_timeout() in between checks, doubling the sleep each time up to MAX_PARK. Senders that unpark the receiving thread (like the ones in section_4/channel_blocking.rs and the *_main examples) wake us straight away, as park_timeout() returns early on unpark().
+ *
+ * That is still polling, and MAX_PARK is the trade off. An idle select wakes up and checks every receiver once per MAX_PARK, and a message from a sender that does not unpark us can wait up to MAX_PARK before select notices it. With a 1ms cap an idle select cost a thousand wake ups a second, so the cap is 50ms, and latency sensitive callers should unpark the selecting thread after sending.
+ *
+ * Each check starts from a random receiver, so one busy channel cannot starve the others.
+ */
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+}
+
+// The longest select sleeps between checks, see above for the trade off
+const MAX_PARK: Duration = Duration::from_millis(50);
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Self {
+            receivers: Vec::new(),
+        }
+    }
+
+    ///
+    /// Adds a receiver to the set, and returns the index select() will report when it is ready
+    ///
+    pub fn recv(&mut self, receiver: &'a dyn Selectable) -> usize {
+        self.receivers.push(receiver);
+        self.receivers.len() - 1
+    }
+
+    pub fn try_select(&self) -> Option<usize> {
+        if self.receivers.is_empty() {
+            return None;
+        }
+
+        let start = rand::thread_rng().gen_range(0..self.receivers.len());
+        (0..self.receivers.len())
+            .map(|i| (start + i) % self.receivers.len())
+            .find(|&i| self.receivers[i].is_ready())
+    }
+
+    pub fn select(&self) -> usize {
+        self.select_deadline(None).unwrap()
+    }
+
+    pub fn select_timeout(&self, timeout: Duration) -> Option<usize> {
+        self.select_deadline(Some(Instant::now() + timeout))
+    }
+
+    fn select_deadline(&self, deadline: Option<Instant>) -> Option<usize> {
+        assert!(
+            deadline.is_some() || !self.receivers.is_empty(),
+            "select() with no receivers would block forever"
+        );
+
+        let mut park = Duration::from_micros(1);
+        loop {
+            if let Some(index) = self.try_select() {
+                return Some(index);
+            }
+
+            let mut timeout = park;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                timeout = timeout.min(deadline - now);
+            }
+
+            thread::park_timeout(timeout);
+            park = (park * 2).min(MAX_PARK);
+        }
+    }
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn select_main() {
+    let (work_sender, work) = channel_mpsc_linked::channel::<i32>();
+    let (shutdown_sender, shutdown) = channel_sender_receiver::channel::<()>();
+
+    let mut select = Select::new();
+    let work_index = select.recv(&work);
+    let shutdown_index = select.recv(&shutdown);
+
+    thread::scope(|s| {
+        let t = thread::current();
+        s.spawn(move || {
+            for i in 0..5 {
+                work_sender.send(i);
+                thread::sleep(Duration::from_millis(100));
+            }
+            shutdown_sender.send(());
+            t.unpark();
+            // keep the work channel connected till after shutdown has been sent
+            drop(work_sender);
+        });
+
+        loop {
+            match select.select_timeout(Duration::from_secs(1)) {
+                Some(index) if index == work_index => {
+                    if let Some(item) = work.try_receive() {
+                        println!("work {:?}", item);
+                    }
+                }
+                Some(index) if index == shutdown_index => {
+                    shutdown.receive();
+                    println!("shutdown");
+                    break;
+                }
+                _ => println!("waiting..."),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Select;
+    use crate::section_4::{channel_mpsc_linked, channel_ring_buffer, channel_sender_receiver};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn reports_the_receiver_that_is_ready() {
+        let ring = channel_ring_buffer::Channel::new(4);
+        let (sender, receiver) = channel_sender_receiver::channel();
+
+        let mut select = Select::new();
+        let ring_index = select.recv(&ring);
+        let oneshot_index = select.recv(&receiver);
+        assert_eq!(select.try_select(), None);
+
+        ring.send(1);
+        assert_eq!(select.try_select(), Some(ring_index));
+        assert_eq!(ring.receive(), 1);
+
+        sender.send("hello");
+        assert_eq!(select.select(), oneshot_index);
+        assert_eq!(receiver.receive(), "hello");
+    }
+
+    #[test]
+    fn timeout_elapses_when_nothing_is_ready() {
+        let ring = channel_ring_buffer::Channel::<i32>::new(4);
+        let mut select = Select::new();
+        select.recv(&ring);
+
+        let start = Instant::now();
+        assert_eq!(select.select_timeout(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn wakes_when_a_message_is_sent_from_another_thread() {
+        let (_work_sender, work) = channel_mpsc_linked::channel::<i32>();
+        let (shutdown_sender, shutdown) = channel_mpsc_linked::channel::<()>();
+
+        let mut select = Select::new();
+        select.recv(&work);
+        let shutdown_index = select.recv(&shutdown);
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            shutdown_sender.send(());
+        });
+
+        assert_eq!(
+            select.select_timeout(Duration::from_secs(5)),
+            Some(shutdown_index)
+        );
+        assert_eq!(shutdown.try_receive(), Some(()));
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io,
+    marker::PhantomData,
+    mem,
+    os::fd::{AsRawFd, FromRawFd},
+    path::Path,
+    ptr,
+    sync::atomic::{self, AtomicU32, Ordering},
+};
+
+use super::CachePadded;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"SHMSPSC1");
+
+// State of each end, kept in the shared header so the other process can see it
+const DETACHED: u32 = 0;
+const ATTACHED: u32 = 1;
+const GONE: u32 = 2;
+
+// A futex word the other side bumps to wake us, and a flag so it only makes the syscall when we are asleep
+struct Wait {
+    event: AtomicU32,
+    waiting: AtomicU32,
+}
+
+// Sits at the start of the shared mapping, the slots follow it
+#[repr(C)]
+struct Header {
+    magic: u64,
+    capacity: u32,
+    slot_size: u32,
+    slot_align: u32,
+    producer: AtomicU32,
+    consumer: AtomicU32,
+    // Next position to read, only written by the Consumer
+    head: CachePadded<AtomicU32>,
+    // Next position to write, only written by the Producer
+    tail: CachePadded<AtomicU32>,
+    // The Consumer sleeps on this whilst the buffer is empty
+    data: CachePadded<Wait>,
+    // The Producer sleeps on this whilst the buffer is full
+    space: CachePadded<Wait>,
+}
+
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is only reached through the Producer or Consumer that owns it
+unsafe impl Send for Mapping {}
+
+/**
+ * section_4/spsc_ring_buffer.rs shares its ring between two threads through an Arc. Here the ring lives in a file (or a memfd) that is mmap'd by both sides with MAP_SHARED, so the Producer and Consumer can be in different processes on the same host.
+ *
+ * The publication is the same Release-Acquire pairing from section_3/release_acquire.rs, with tail playing READY for the messages and head playing READY for the free slots. Atomics in shared memory work the same across processes as across threads, both processes map the same physical page and the CPU's cache coherence does the rest.
+ *
+ * The payload has to be Copy. The bytes are copied into the shared slots as they are, so a message must not hold pointers (a &str or Box would point into the other process's address space) and never needs dropping.
+ *
+ * Positions are u32 and wrap, so the capacity must be a power of two to keep position % capacity in step across the wrap.
+ *
+ * Blocking uses a futex (Linux only) rather than thread::park, as the thread to wake is in another process. Each side has a Wait in the header:
+ * - the sleeper sets waiting, has a SeqCst fence, reads event, rechecks the buffer, then calls FUTEX_WAIT on event with the value it read
+ * - the other side stores head/tail, has a SeqCst fence, and only if waiting is set bumps event and calls FUTEX_WAKE
+ *
+ * The fences mean either the sleeper sees the new head/tail, or the waker sees waiting (same reasoning as section_3/seqcst_ordering.rs). The kernel only puts the sleeper to sleep if event still holds the value it read, so a wake between the recheck and the syscall is not lost. FUTEX_PRIVATE_FLAG is not used, as it only works within a single process.
+ *
+ * The header records whether each end is attached or gone, so only one Producer and one Consumer can attach, and dropping an end wakes the other one. A process that is killed never marks its end as gone, the other end then waits forever.
+ */
+pub struct Producer<T> {
+    mapping: Mapping,
+    cached_head: u32,
+    _marker: PhantomData<T>,
+}
+
+pub struct Consumer<T> {
+    mapping: Mapping,
+    cached_tail: u32,
+    _marker: PhantomData<T>,
+}
+
+fn slots_offset<T>() -> usize {
+    mem::size_of::<Header>().next_multiple_of(mem::align_of::<T>())
+}
+
+fn mapping_len<T>(capacity: u32) -> usize {
+    slots_offset::<T>() + capacity as usize * mem::size_of::<T>()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+///
+/// Creates (or truncates) the file at path and sets it up as an empty ring buffer. The other process opens the same path read/write and attaches to it.
+///
+pub fn create<T: Copy>(path: impl AsRef<Path>, capacity: u32) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    init::<T>(&file, capacity)?;
+    Ok(file)
+}
+
+///
+/// Same as create() but backed by an anonymous memfd, for sharing with a child process after fork() or through /proc/<pid>/fd
+///
+pub fn create_memfd<T: Copy>(name: &str, capacity: u32) -> io::Result<File> {
+    let name = CString::new(name).map_err(|_| invalid("name contains a nul byte"))?;
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: memfd_create has just returned a new fd that nothing else owns
+    let file = unsafe { File::from_raw_fd(fd) };
+    init::<T>(&file, capacity)?;
+    Ok(file)
+}
+
+fn init<T>(file: &File, capacity: u32) -> io::Result<()> {
+    assert!(
+        capacity.is_power_of_two(),
+        "capacity must be a power of two"
+    );
+    file.set_len(mapping_len::<T>(capacity) as u64)?;
+
+    let mapping = Mapping::new(file)?;
+    // Safety: the file is new (or truncated), so nothing else is using the header yet
+    unsafe {
+        ptr::write(
+            mapping.ptr as *mut Header,
+            Header {
+                magic: MAGIC,
+                capacity,
+                slot_size: mem::size_of::<T>() as u32,
+                slot_align: mem::align_of::<T>() as u32,
+                producer: AtomicU32::new(DETACHED),
+                consumer: AtomicU32::new(DETACHED),
+                head: CachePadded(AtomicU32::new(0)),
+                tail: CachePadded(AtomicU32::new(0)),
+                data: CachePadded(Wait::new()),
+                space: CachePadded(Wait::new()),
+            },
+        )
+    };
+    Ok(())
+}
+
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    // Returns straight away if word no longer holds expected, spurious wake ups are handled by the caller's loop
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAIT,
+            expected,
+            ptr::null::<libc::timespec>(),
+        )
+    };
+}
+
+fn futex_wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAKE,
+            1,
+        )
+    };
+}
+
+impl Wait {
+    fn new() -> Self {
+        Self {
+            event: AtomicU32::new(0),
+            waiting: AtomicU32::new(0),
+        }
+    }
+
+    ///
+    /// Sleeps till woken unless ready() is already true
+    ///
+    fn wait(&self, ready: impl Fn() -> bool) {
+        self.waiting.store(1, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+        let event = self.event.load(Ordering::Acquire);
+        if !ready() {
+            futex_wait(&self.event, event);
+        }
+        self.waiting.store(0, Ordering::Relaxed);
+    }
+
+    ///
+    /// Called after a store the sleeper is waiting to see
+    ///
+    fn wake(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.waiting.load(Ordering::Relaxed) != 0 {
+            self.event.fetch_add(1, Ordering::Release);
+            futex_wake(&self.event);
+        }
+    }
+}
+
+impl Mapping {
+    fn new(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len < mem::size_of::<Header>() {
+            return Err(invalid("file is too small to hold a ring buffer"));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        // Safety: the mapping is page aligned and at least as large as the header
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    fn slot<T>(&self, position: u32) -> *mut T {
+        let index = (position % self.header().capacity) as usize;
+        unsafe { self.ptr.add(slots_offset::<T>()).cast::<T>().add(index) }
+    }
+
+    ///
+    /// Checks the file was set up by create() for the same T, then marks the given end as attached
+    ///
+    fn attach<T>(file: &File, end: impl Fn(&Header) -> &AtomicU32) -> io::Result<Self> {
+        let mapping = Self::new(file)?;
+        let header = mapping.header();
+        if header.magic != MAGIC {
+            return Err(invalid("file is not a shared ring buffer"));
+        }
+        if header.slot_size as usize != mem::size_of::<T>()
+            || header.slot_align as usize != mem::align_of::<T>()
+        {
+            return Err(invalid("ring buffer was created for a different type"));
+        }
+        if !header.capacity.is_power_of_two() || mapping.len < mapping_len::<T>(header.capacity) {
+            return Err(invalid("ring buffer is truncated"));
+        }
+
+        end(header)
+            .compare_exchange(DETACHED, ATTACHED, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "this end of the ring buffer has already been attached",
+                )
+            })?;
+        Ok(mapping)
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    pub fn attach(file: &File) -> io::Result<Self> {
+        let mapping = Mapping::attach::<T>(file, |header| &header.producer)?;
+        let cached_head = mapping.header().head.load(Ordering::Acquire);
+        Ok(Self {
+            mapping,
+            cached_head,
+            _marker: PhantomData,
+        })
+    }
+
+    ///
+    /// Gives the message back if the buffer is full or the Consumer has gone
+    ///
+    pub fn try_send(&mut self, message: T) -> Result<(), T> {
+        let header = self.mapping.header();
+        if header.consumer.load(Ordering::Relaxed) == GONE {
+            return Err(message);
+        }
+
+        let tail = header.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.cached_head) == header.capacity {
+            self.cached_head = header.head.load(Ordering::Acquire);
+            if tail.wrapping_sub(self.cached_head) == header.capacity {
+                return Err(message);
+            }
+        }
+
+        // Safety: the slot is between tail and head + capacity, so the Consumer is not reading it
+        unsafe { self.mapping.slot::<T>(tail).write(message) };
+        header.tail.store(tail.wrapping_add(1), Ordering::Release);
+        header.data.wake();
+        Ok(())
+    }
+
+    ///
+    /// Blocks whilst the buffer is full, gives the message back if the Consumer has gone
+    ///
+    pub fn send(&mut self, mut message: T) -> Result<(), T> {
+        loop {
+            message = match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(message) => message,
+            };
+
+            let header = self.mapping.header();
+            if header.consumer.load(Ordering::Relaxed) == GONE {
+                return Err(message);
+            }
+            let tail = header.tail.load(Ordering::Relaxed);
+            header.space.wait(|| {
+                tail.wrapping_sub(header.head.load(Ordering::Acquire)) < header.capacity
+                    || header.consumer.load(Ordering::Relaxed) == GONE
+            });
+        }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        let header = self.mapping.header();
+        header.producer.store(GONE, Ordering::Release);
+        header.data.wake();
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    pub fn attach(file: &File) -> io::Result<Self> {
+        let mapping = Mapping::attach::<T>(file, |header| &header.consumer)?;
+        let cached_tail = mapping.header().tail.load(Ordering::Acquire);
+        Ok(Self {
+            mapping,
+            cached_tail,
+            _marker: PhantomData,
+        })
+    }
+
+    ///
+    /// Returns None if the buffer is empty
+    ///
+    pub fn try_receive(&mut self) -> Option<T> {
+        let header = self.mapping.header();
+        let head = header.head.load(Ordering::Relaxed);
+        if self.cached_tail == head {
+            self.cached_tail = header.tail.load(Ordering::Acquire);
+            if self.cached_tail == head {
+                return None;
+            }
+        }
+
+        // Safety: the Acquire load of tail makes the Producer's write visible, and it will not touch the slot till we move head on
+        let message = unsafe { self.mapping.slot::<T>(head).read() };
+        header.head.store(head.wrapping_add(1), Ordering::Release);
+        header.space.wake();
+        Some(message)
+    }
+
+    ///
+    /// Blocks till there is a message, returns None once the Producer has gone and the buffer is empty
+    ///
+    pub fn receive(&mut self) -> Option<T> {
+        loop {
+            if let Some(message) = self.try_receive() {
+                return Some(message);
+            }
+
+            let header = self.mapping.header();
+            if header.producer.load(Ordering::Acquire) == GONE {
+                // Anything sent just before the Producer was dropped
+                return self.try_receive();
+            }
+            let head = header.head.load(Ordering::Relaxed);
+            header.data.wait(|| {
+                header.tail.load(Ordering::Acquire) != head
+                    || header.producer.load(Ordering::Relaxed) == GONE
+            });
+        }
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        let header = self.mapping.header();
+        header.consumer.store(GONE, Ordering::Release);
+        header.space.wake();
+    }
+}
+
+pub fn shared_memory_spsc_main() {
+    let file = create_memfd::<u64>("shared-memory-spsc", 8).unwrap();
+
+    // The child process gets a copy of the fd, both map the same memfd
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+        0 => {
+            let mut producer = Producer::<u64>::attach(&file).unwrap();
+            for i in 1..=100 {
+                producer.send(i).unwrap();
+            }
+            drop(producer);
+            unsafe { libc::_exit(0) };
+        }
+        child => {
+            let mut consumer = Consumer::<u64>::attach(&file).unwrap();
+            let mut total = 0;
+            while let Some(n) = consumer.receive() {
+                total += n;
+            }
+            println!("process {} sent a total of {total}", child);
+
+            unsafe { libc::waitpid(child, ptr::null_mut(), 0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create, create_memfd, Consumer, Producer};
+    use std::{env, fs, io, process, thread};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Reading {
+        sensor: u32,
+        value: f64,
+    }
+
+    #[test]
+    fn messages_pass_between_two_mappings_of_the_same_file() {
+        let path = env::temp_dir().join(format!("rust-atomics-shm-{}", process::id()));
+        let file = create::<u64>(&path, 4).unwrap();
+        // A separate open and mmap, as another process would do
+        let other = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut producer = Producer::<u64>::attach(&file).unwrap();
+        let mut consumer = Consumer::<u64>::attach(&other).unwrap();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..1000 {
+                    producer.send(i).unwrap();
+                }
+            });
+
+            for i in 0..1000 {
+                assert_eq!(consumer.receive(), Some(i));
+            }
+            // The Producer has been dropped
+            assert_eq!(consumer.receive(), None);
+        });
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn each_end_attaches_once_and_the_type_is_checked() {
+        let file = create_memfd::<u32>("attach", 2).unwrap();
+
+        assert_eq!(
+            Consumer::<u64>::attach(&file).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut producer = Producer::<u32>::attach(&file).unwrap();
+        assert_eq!(
+            Producer::<u32>::attach(&file).err().unwrap().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        assert_eq!(producer.try_send(1), Ok(()));
+        assert_eq!(producer.try_send(2), Ok(()));
+        assert_eq!(producer.try_send(3), Err(3));
+
+        let consumer = Consumer::<u32>::attach(&file).unwrap();
+        drop(consumer);
+        // Nobody is left to receive it
+        assert_eq!(producer.send(4), Err(4));
+    }
+
+    #[test]
+    fn messages_cross_a_process_boundary() {
+        let file = create_memfd::<Reading>("fork", 16).unwrap();
+
+        match unsafe { libc::fork() } {
+            -1 => panic!("fork failed"),
+            0 => {
+                // Only atomics and syscalls from here on, the child must not allocate (another test thread may have held the allocator's lock when we forked)
+                let sent = Producer::<Reading>::attach(&file).is_ok_and(|mut producer| {
+                    (0..10_000).all(|i| {
+                        producer
+                            .send(Reading {
+                                sensor: i,
+                                value: i as f64 / 2.0,
+                            })
+                            .is_ok()
+                    })
+                });
+                unsafe { libc::_exit(if sent { 0 } else { 1 }) };
+            }
+            child => {
+                let mut consumer = Consumer::<Reading>::attach(&file).unwrap();
+                for i in 0..10_000 {
+                    assert_eq!(
+                        consumer.receive(),
+                        Some(Reading {
+                            sensor: i,
+                            value: i as f64 / 2.0,
+                        })
+                    );
+                }
+                assert_eq!(consumer.receive(), None);
+
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+            }
+        }
+    }
+}
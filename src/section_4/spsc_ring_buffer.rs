@@ -0,0 +1,530 @@
+use std::{
+    cell::UnsafeCell,
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/**
+ * Pads and aligns T to 64 bytes (the cache line size on most x86_64 and aarch64 CPUs).
+ *
+ * When two atomics that are written by different threads sit on the same cache line, every write from one core invalidates the line in the other core's cache, even though they never touch the same atomic (false sharing). Giving head and tail a cache line each stops the producer and consumer fighting over it.
+ */
+#[derive(Debug, Default)]
+#[repr(align(64))]
+pub struct CachePadded<T>(pub T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+struct RingBuffer<T> {
+    // Next position to read, only written by the Consumer
+    head: CachePadded<AtomicUsize>,
+    // Next position to write, only written by the Producer
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+unsafe impl<T> Sync for RingBuffer<T> where T: Send {}
+
+/**
+ * A fixed capacity single producer single consumer ring buffer, for the one producer one consumer pipelines from section_1/thread_parking.rs where a Mutex<VecDeque> or a general channel is overkill.
+ *
+ * With only one writer to each index, there are no compare_exchange loops, every push and pop finishes in a fixed number of steps (wait-free):
+ * - The Producer writes the message into the slot at tail, then stores tail + 1 with Release
+ * - The Consumer loads tail with Acquire, so it sees the message write, reads the slot, then stores head + 1 with Release
+ * - The Producer loads head with Acquire before reusing a slot, so it never overwrites a message the Consumer is still reading
+ *
+ * This is the Release-Acquire publication from section_3/release_acquire.rs, with tail playing READY for the messages and head playing READY for the free slots.
+ *
+ * Each side also caches the last value it saw of the other side's index, so it only touches the other side's cache line when the buffer looks full (or empty). push_slice and pop_into move many messages with a single Release store.
+ *
+ * reserve() and read() give in place access to a slot, for messages that are too large to move twice. As there is only one Producer, a Reservation that is dropped without commit() simply never moves tail on, so the slot is reused by the next push.
+ *
+ * There is exactly one of each end, so the endpoint counts come from the Arc's strong count, the other end is gone once it drops to 1. The buffer is closed once either end has been dropped.
+ */
+pub struct Producer<T> {
+    ring: Arc<RingBuffer<T>>,
+    cached_head: usize,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<RingBuffer<T>>,
+    cached_tail: usize,
+}
+
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+
+    let arc = Arc::new(RingBuffer {
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+        buffer: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+    });
+    (
+        Producer {
+            ring: arc.clone(),
+            cached_head: 0,
+        },
+        Consumer {
+            ring: arc,
+            cached_tail: 0,
+        },
+    )
+}
+
+impl<T> RingBuffer<T> {
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.buffer[position % self.capacity()].get()
+    }
+
+    fn len(&self) -> usize {
+        // head first, tail never moves backwards so it cannot be behind the head we read
+        let head = self.head.load(Ordering::Relaxed);
+        self.tail.load(Ordering::Relaxed).wrapping_sub(head)
+    }
+}
+
+impl<T> Producer<T> {
+    ///
+    /// Number of free slots, only reloading the Consumer's head if the cached value says we are short
+    ///
+    fn free_slots(&mut self, tail: usize, wanted: usize) -> usize {
+        let capacity = self.ring.capacity();
+        let mut free = capacity - tail.wrapping_sub(self.cached_head);
+        if free < wanted {
+            self.cached_head = self.ring.head.load(Ordering::Acquire);
+            free = capacity - tail.wrapping_sub(self.cached_head);
+        }
+        free
+    }
+
+    ///
+    /// Gives the message back if the buffer is full
+    ///
+    pub fn push(&mut self, message: T) -> Result<(), T> {
+        match self.reserve() {
+            Some(reservation) => {
+                reservation.write(message);
+                Ok(())
+            }
+            None => Err(message),
+        }
+    }
+
+    ///
+    /// The next free slot for the caller to write into, None if the buffer is full
+    ///
+    pub fn reserve(&mut self) -> Option<Reservation<'_, T>> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        if self.free_slots(tail, 1) == 0 {
+            return None;
+        }
+
+        Some(Reservation {
+            ring: &self.ring,
+            tail,
+        })
+    }
+
+    ///
+    /// Clones as many messages from the front of the slice as there is room for, returns how many were pushed
+    ///
+    pub fn push_slice(&mut self, messages: &[T]) -> usize
+    where
+        T: Clone,
+    {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let count = self.free_slots(tail, messages.len()).min(messages.len());
+
+        for (i, message) in messages[..count].iter().enumerate() {
+            unsafe { (*self.ring.slot(tail.wrapping_add(i))).write(message.clone()) };
+        }
+        // Publish every message at once
+        self.ring
+            .tail
+            .store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.ring.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.ring.capacity())
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        1
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        Arc::strong_count(&self.ring) - 1
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.ring) < 2
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn is_ready(&self) -> bool {
+        self.ring.tail.load(Ordering::Relaxed) != self.ring.head.load(Ordering::Relaxed)
+    }
+
+    fn available(&mut self, head: usize, wanted: usize) -> usize {
+        let mut available = self.cached_tail.wrapping_sub(head);
+        if available < wanted {
+            self.cached_tail = self.ring.tail.load(Ordering::Acquire);
+            available = self.cached_tail.wrapping_sub(head);
+        }
+        available
+    }
+
+    ///
+    /// Returns None if the buffer is empty
+    ///
+    pub fn pop(&mut self) -> Option<T> {
+        self.read().map(ReadGuard::into_inner)
+    }
+
+    ///
+    /// The next message to be read in place, None if the buffer is empty
+    ///
+    pub fn read(&mut self) -> Option<ReadGuard<'_, T>> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        if self.available(head, 1) == 0 {
+            return None;
+        }
+
+        Some(ReadGuard {
+            ring: &self.ring,
+            head,
+        })
+    }
+
+    ///
+    /// Pops every message that is available right now
+    ///
+    #[allow(unused)]
+    pub fn try_iter(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+
+    ///
+    /// Moves as many messages as are available (up to the slice length) into the slice, returns how many were written
+    ///
+    pub fn pop_into(&mut self, buffer: &mut [T]) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let count = self.available(head, buffer.len()).min(buffer.len());
+
+        for (i, slot) in buffer[..count].iter_mut().enumerate() {
+            *slot = unsafe { (*self.ring.slot(head.wrapping_add(i))).assume_init_read() };
+        }
+        // Hand every slot back to the Producer at once
+        self.ring
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.ring.len() == 0
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> Option<usize> {
+        Some(self.ring.capacity())
+    }
+
+    #[allow(unused)]
+    pub fn receiver_count(&self) -> usize {
+        1
+    }
+
+    #[allow(unused)]
+    pub fn sender_count(&self) -> usize {
+        Arc::strong_count(&self.ring) - 1
+    }
+
+    #[allow(unused)]
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.ring) < 2
+    }
+}
+
+///
+/// The slot at tail, write the message into slot() then commit(). Dropping it without committing leaves the slot free.
+///
+pub struct Reservation<'a, T> {
+    ring: &'a RingBuffer<T>,
+    tail: usize,
+}
+
+impl<T> Reservation<'_, T> {
+    pub fn slot(&mut self) -> &mut MaybeUninit<T> {
+        // Safety: the slot is between tail and head + capacity, so the Consumer is not reading it
+        unsafe { &mut *self.ring.slot(self.tail) }
+    }
+
+    ///
+    /// Publishes the slot to the Consumer
+    ///
+    /// # Safety
+    /// The slot must have been initialised through slot()
+    ///
+    pub unsafe fn commit(self) {
+        self.ring
+            .tail
+            .store(self.tail.wrapping_add(1), Ordering::Release);
+    }
+
+    pub fn write(mut self, message: T) {
+        self.slot().write(message);
+        // Safety: we have just written the message
+        unsafe { self.commit() };
+    }
+}
+
+///
+/// The message at head, read in place. The message is dropped and the slot handed back to the Producer when the guard is dropped.
+///
+pub struct ReadGuard<'a, T> {
+    ring: &'a RingBuffer<T>,
+    head: usize,
+}
+
+impl<T> ReadGuard<'_, T> {
+    ///
+    /// Moves the message out and hands the slot back
+    ///
+    pub fn into_inner(self) -> T {
+        // Safety: the Acquire load of tail makes the Producer's write visible, and it will not touch the slot till we move head on
+        let message = unsafe { (*self.ring.slot(self.head)).assume_init_read() };
+        self.release();
+        mem::forget(self);
+        message
+    }
+
+    fn release(&self) {
+        self.ring
+            .head
+            .store(self.head.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { (*self.ring.slot(self.head)).assume_init_ref() }
+    }
+}
+
+impl<T> DerefMut for ReadGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { (*self.ring.slot(self.head)).assume_init_mut() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { (*self.ring.slot(self.head)).assume_init_drop() };
+        self.release();
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        let head = *self.head.0.get_mut();
+        let tail = *self.tail.0.get_mut();
+        let capacity = self.capacity();
+        for position in head..tail {
+            unsafe {
+                self.buffer[position % capacity]
+                    .get_mut()
+                    .assume_init_drop()
+            };
+        }
+    }
+}
+
+pub fn spsc_ring_buffer_main() {
+    let (mut producer, mut consumer) = ring_buffer::<u64>(16);
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            let numbers: Vec<u64> = (1..=100).collect();
+            let mut sent = 0;
+            while sent < numbers.len() {
+                sent += producer.push_slice(&numbers[sent..]);
+                thread::yield_now();
+            }
+        });
+
+        let mut batch = [0; 8];
+        let mut total = 0;
+        let mut received = 0;
+        while received < 100 {
+            let count = consumer.pop_into(&mut batch);
+            if count == 0 {
+                thread::yield_now();
+            }
+            total += batch[..count].iter().sum::<u64>();
+            received += count;
+        }
+        println!("total {:?}", total);
+        assert_eq!(total, 5050);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ring_buffer;
+    use crate::test_util::DetectDrop;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    #[test]
+    fn push_and_pop_wrap_around_the_buffer() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+
+        for round in 0..10 {
+            assert_eq!(producer.push(round), Ok(()));
+            assert_eq!(producer.push(round + 1), Ok(()));
+            assert_eq!(producer.push(round + 2), Ok(()));
+            assert_eq!(producer.push(round + 3), Err(round + 3));
+
+            assert_eq!(consumer.pop(), Some(round));
+            assert_eq!(consumer.pop(), Some(round + 1));
+            assert_eq!(consumer.pop(), Some(round + 2));
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn batch_operations_only_move_what_fits() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        assert_eq!(producer.push_slice(&[1, 2, 3, 4, 5, 6]), 4);
+        assert_eq!(producer.push_slice(&[5]), 0);
+
+        let mut batch = [0; 3];
+        assert_eq!(consumer.pop_into(&mut batch), 3);
+        assert_eq!(batch, [1, 2, 3]);
+
+        assert_eq!(producer.push_slice(&[5, 6]), 2);
+        assert_eq!(consumer.pop_into(&mut batch), 3);
+        assert_eq!(batch, [4, 5, 6]);
+        assert_eq!(consumer.pop_into(&mut batch), 0);
+
+        assert_eq!(producer.push_slice(&[7, 8]), 2);
+        assert_eq!(consumer.try_iter().collect::<Vec<_>>(), [7, 8]);
+    }
+
+    #[test]
+    fn reserved_slots_are_written_and_read_in_place() {
+        let (mut producer, mut consumer) = ring_buffer(2);
+
+        // Abandoned, the slot stays free
+        assert!(producer.reserve().is_some());
+        assert!(consumer.read().is_none());
+
+        let mut reservation = producer.reserve().unwrap();
+        reservation.slot().write(String::from("hello"));
+        unsafe { reservation.commit() };
+        producer.reserve().unwrap().write(String::from("world"));
+        assert!(producer.reserve().is_none());
+
+        let mut guard = consumer.read().unwrap();
+        guard.push('!');
+        assert_eq!(*guard, "hello!");
+        drop(guard);
+        assert_eq!(consumer.pop().as_deref(), Some("world"));
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn messages_cross_threads_in_order() {
+        let (mut producer, mut consumer) = ring_buffer(8);
+
+        let t = thread::spawn(move || {
+            for i in 0..10_000 {
+                let mut message = i;
+                while let Err(m) = producer.push(message) {
+                    message = m;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < 10_000 {
+            match consumer.pop() {
+                Some(message) => {
+                    assert_eq!(message, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn unpopped_messages_are_dropped_with_the_buffer() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let (mut producer, mut consumer) = ring_buffer(4);
+        for _ in 0..3 {
+            assert!(producer.push(DetectDrop(num_drops.clone())).is_ok());
+        }
+
+        drop(consumer.pop());
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+        assert_eq!(producer.len(), 2);
+        assert_eq!(consumer.capacity(), Some(4));
+        assert!(!consumer.is_closed());
+
+        drop(producer);
+        assert!(consumer.is_closed());
+        assert_eq!(consumer.sender_count(), 0);
+        drop(consumer);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 3);
+    }
+}
@@ -1,13 +1,16 @@
 use std::{
+    alloc::{self, Layout},
     cell::UnsafeCell,
-    mem::ManuallyDrop,
+    mem::{self, ManuallyDrop},
     ops::Deref,
-    ptr::NonNull,
+    ptr::{self, NonNull},
     sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
+// repr(C) so the counts always come first and data is at the end, where an unsized T has to go. See Arc::allocate()
 #[derive(Debug)]
-struct ArcData<T> {
+#[repr(C)]
+struct ArcData<T: ?Sized> {
     /// Number of `Arc`s.
     data_ref_count: AtomicUsize,
     /// Number of `Weak`s, plus one if there are any `Arc`s.
@@ -16,32 +19,21 @@ struct ArcData<T> {
     data: UnsafeCell<ManuallyDrop<T>>,
 }
 
-pub struct Arc<T> {
+pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
-unsafe impl<T: Sync + Send> Send for Arc<T> {}
-unsafe impl<T: Sync + Send> Sync for Arc<T> {}
+unsafe impl<T: Sync + Send + ?Sized> Send for Arc<T> {}
+unsafe impl<T: Sync + Send + ?Sized> Sync for Arc<T> {}
 
-pub struct Weak<T> {
+pub struct Weak<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
-unsafe impl<T: Sync + Send> Send for Weak<T> {}
-unsafe impl<T: Sync + Send> Sync for Weak<T> {}
-
-impl<T> Arc<T> {
-    #[allow(unused)]
-    pub fn new(data: T) -> Arc<T> {
-        Arc {
-            ptr: NonNull::from(Box::leak(Box::new(ArcData {
-                alloc_ref_count: AtomicUsize::new(1),
-                data_ref_count: AtomicUsize::new(1),
-                data: UnsafeCell::new(ManuallyDrop::new(data)),
-            }))),
-        }
-    }
+unsafe impl<T: Sync + Send + ?Sized> Send for Weak<T> {}
+unsafe impl<T: Sync + Send + ?Sized> Sync for Weak<T> {}
 
+impl<T: ?Sized> Arc<T> {
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
@@ -108,7 +100,213 @@ impl<T> Arc<T> {
     }
 }
 
-impl<T> Deref for Arc<T> {
+impl<T> Arc<T> {
+    #[allow(unused)]
+    pub fn new(data: T) -> Arc<T> {
+        Arc {
+            ptr: NonNull::from(Box::leak(Box::new(ArcData {
+                alloc_ref_count: AtomicUsize::new(1),
+                data_ref_count: AtomicUsize::new(1),
+                data: UnsafeCell::new(ManuallyDrop::new(data)),
+            }))),
+        }
+    }
+
+    ///
+    /// Will return T if this is the only Arc, else hands the Arc back. Weak pointers do not stop it, once data_ref_count is 0 they fail to upgrade, and the last of them frees the allocation
+    ///
+    #[allow(unused)]
+    pub fn try_unwrap(arc: Self) -> Result<T, Self> {
+        // Acquire matches Arc::drop's Release decrement, so no other Arc is still reading T
+        if arc
+            .data()
+            .data_ref_count
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(arc);
+        }
+
+        Ok(Self::take_data(arc))
+    }
+
+    ///
+    /// Drops this Arc, returning T if it was the last one. It is the same fetch_sub as Drop, so if the last two Arcs call this at the same time exactly one of them gets T
+    ///
+    #[allow(unused)]
+    pub fn into_inner(arc: Self) -> Option<T> {
+        let arc = ManuallyDrop::new(arc);
+        if arc.data().data_ref_count.fetch_sub(1, Ordering::Release) != 1 {
+            return None;
+        }
+
+        fence(Ordering::Acquire);
+        Some(Self::take_data(ManuallyDrop::into_inner(arc)))
+    }
+
+    ///
+    /// Copy on write. Mutates T in place if this is the only pointer, else gives this Arc its own allocation:
+    /// - other Arcs share T, so it is cloned into a new Arc and they keep the original
+    /// - only Weaks share it, so T is moved into a new Arc, the Weaks are left with the old allocation and can no longer upgrade
+    ///
+    #[allow(unused)]
+    pub fn make_mut(arc: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        // The same lock as get_mut, stops downgrade() making a Weak whilst we check data_ref_count
+        if arc
+            .data()
+            .alloc_ref_count
+            .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            let is_unique = arc.data().data_ref_count.load(Ordering::Relaxed) == 1;
+            arc.data().alloc_ref_count.store(1, Ordering::Release);
+            if is_unique {
+                // Acquire to match Arc::drop's Release decrement
+                fence(Ordering::Acquire);
+            } else {
+                *arc = Arc::new((**arc).clone());
+            }
+        } else if arc
+            .data()
+            .data_ref_count
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            // The only Arc, but there are Weaks. data_ref_count is 0 so they cannot upgrade, and T can be moved out like try_unwrap()
+            let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.get()) };
+            let old = mem::replace(arc, Arc::new(data));
+            // The old allocation belongs to the Weaks now, drop the implicit Weak without running Arc's Drop
+            let old = ManuallyDrop::new(old);
+            drop(Weak { ptr: old.ptr });
+        } else {
+            // Other Arcs, and Weaks too
+            *arc = Arc::new((**arc).clone());
+        }
+
+        unsafe { &mut *arc.data().data.get() }
+    }
+
+    ///
+    /// T if this is the only Arc, else a clone of it
+    ///
+    #[allow(unused)]
+    pub fn unwrap_or_clone(arc: Self) -> T
+    where
+        T: Clone,
+    {
+        Self::try_unwrap(arc).unwrap_or_else(|arc| (*arc).clone())
+    }
+
+    ///
+    /// Moves T out once data_ref_count has reached 0, then drops the implicit Weak the Arcs shared, instead of running Arc's Drop
+    ///
+    fn take_data(arc: Self) -> T {
+        let arc = ManuallyDrop::new(arc);
+        let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.get()) };
+        drop(Weak { ptr: arc.ptr });
+        data
+    }
+}
+
+/**
+ * T can also be unsized, a slice, str or trait object, as Arc<[T]>, Arc<str> or Arc<dyn Trait>. Box::new(ArcData { .. }) cannot build those, it needs a value it can move, so they are made from a Vec, String or Box that already holds one.
+ *
+ * A new allocation is made for ArcData, with the counts and the value in the one block as for a sized T, and the value's bytes are moved into it. The old Vec, String or Box is then freed without dropping the value.
+ *
+ * With repr(C), ArcData's layout is the two counts, then the value at the next offset that suits its alignment, then padding to the alignment of the whole struct. That is what Layout::extend() and pad_to_align() give, and what Layout::for_value() gives for the finished ArcData, so Weak::drop() can still free it with Box::from_raw().
+ *
+ * The pointer to ArcData<T> also has to carry T's metadata, the length of the slice or the vtable of the trait object. A slice pointer can be made from the new address and the length, a trait object pointer has to be copied from the Box's and given the new address.
+ *
+ * Only the constructors are new. Everything else already works through &ArcData<T>, and the methods that move T out (try_unwrap() and the like) still need a sized T.
+ */
+impl<T: ?Sized> Arc<T> {
+    ///
+    /// Allocates ArcData for a value with value_layout, both counts at 1. data is left for the caller to fill in. with_metadata turns the address of the allocation into a pointer to ArcData<T> carrying the value's metadata. Also returns the layout it allocated, so the caller can check it against Layout::for_value() once data is filled in.
+    ///
+    unsafe fn allocate(
+        value_layout: Layout,
+        with_metadata: impl FnOnce(*mut u8) -> *mut ArcData<T>,
+    ) -> (NonNull<ArcData<T>>, Layout) {
+        let layout = Layout::new::<ArcData<()>>()
+            .extend(value_layout)
+            .unwrap()
+            .0
+            .pad_to_align();
+
+        let mem = alloc::alloc(layout);
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        let arc_data = with_metadata(mem);
+        ptr::addr_of_mut!((*arc_data).data_ref_count).write(AtomicUsize::new(1));
+        ptr::addr_of_mut!((*arc_data).alloc_ref_count).write(AtomicUsize::new(1));
+        (NonNull::new_unchecked(arc_data), layout)
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Arc<T> {
+    ///
+    /// Moves the value out of the Box, works for Box<dyn Trait> and Box<[T]> too
+    ///
+    fn from(value: Box<T>) -> Self {
+        let value_layout = Layout::for_value(&*value);
+        let value = Box::into_raw(value);
+        unsafe {
+            let (ptr, layout) = Self::allocate(value_layout, |mem| {
+                // Keeps the metadata of the Box's pointer, and swaps its address for mem. There is no stable way to build a pointer to an unsized T from parts, this relies on the address being the first half of a wide pointer
+                let mut arc_data = value as *mut ArcData<T>;
+                ptr::write(&mut arc_data as *mut *mut ArcData<T> as *mut *mut u8, mem);
+                arc_data
+            });
+            ptr::copy_nonoverlapping(
+                value as *const u8,
+                ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut u8,
+                value_layout.size(),
+            );
+            // Weak::drop() frees with this layout, so a pointer built with the wrong metadata shows up here
+            debug_assert_eq!(Layout::for_value(&*ptr.as_ptr()), layout);
+            // Frees the Box, the value now belongs to ArcData
+            drop(Box::from_raw(value as *mut ManuallyDrop<T>));
+            Arc { ptr }
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(mut value: Vec<T>) -> Self {
+        let len = value.len();
+        unsafe {
+            let (ptr, layout) = Self::allocate(Layout::array::<T>(len).unwrap(), |mem| {
+                ptr::slice_from_raw_parts_mut(mem as *mut T, len) as *mut ArcData<[T]>
+            });
+            ptr::copy_nonoverlapping(
+                value.as_ptr(),
+                ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut T,
+                len,
+            );
+            debug_assert_eq!(Layout::for_value(&*ptr.as_ptr()), layout);
+            // The elements have been moved, the Vec only frees its buffer
+            value.set_len(0);
+            Arc { ptr }
+        }
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(value: String) -> Self {
+        let bytes = ManuallyDrop::new(Arc::<[u8]>::from(value.into_bytes()));
+        // str has the same layout and metadata as [u8], and the bytes came from a String so are valid UTF-8
+        Arc {
+            ptr: unsafe { NonNull::new_unchecked(bytes.ptr.as_ptr() as *mut ArcData<str>) },
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Arc<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -116,7 +314,7 @@ impl<T> Deref for Arc<T> {
     }
 }
 
-impl<T> Weak<T> {
+impl<T: ?Sized> Weak<T> {
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
@@ -143,7 +341,7 @@ impl<T> Weak<T> {
     }
 }
 
-impl<T> Clone for Weak<T> {
+impl<T: ?Sized> Clone for Weak<T> {
     fn clone(&self) -> Self {
         if self.data().alloc_ref_count.fetch_add(1, Ordering::Relaxed) > usize::MAX >> 1 {
             std::process::abort();
@@ -153,7 +351,7 @@ impl<T> Clone for Weak<T> {
     }
 }
 
-impl<T> Drop for Weak<T> {
+impl<T: ?Sized> Drop for Weak<T> {
     fn drop(&mut self) {
         if self.data().alloc_ref_count.fetch_sub(1, Ordering::Release) == 1 {
             fence(Ordering::Acquire);
@@ -164,7 +362,7 @@ impl<T> Drop for Weak<T> {
     }
 }
 
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized> Clone for Arc<T> {
     fn clone(&self) -> Self {
         if self.data().data_ref_count.fetch_add(1, Ordering::Relaxed) > usize::MAX >> 1 {
             std::process::abort();
@@ -174,60 +372,144 @@ impl<T> Clone for Arc<T> {
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized> Drop for Arc<T> {
     fn drop(&mut self) {
         if self.data().data_ref_count.fetch_sub(1, Ordering::Release) == 1 {
             fence(Ordering::Acquire);
             unsafe {
                 ManuallyDrop::drop(&mut *self.data().data.get());
             }
+            // The Arcs share a single implicit Weak (the + 1 in alloc_ref_count), so only the last Arc drops it
+            drop(Weak { ptr: self.ptr });
         }
-
-        drop(Weak { ptr: self.ptr });
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Arc;
+    use crate::test_util::DetectDrop;
     use std::{
-        sync::atomic::{AtomicUsize, Ordering},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc as StdArc,
+        },
         thread,
     };
 
-    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
-
-    struct DetectDrop;
-    impl Drop for DetectDrop {
-        fn drop(&mut self) {
-            NUM_DROPS.fetch_add(1, Ordering::Relaxed);
-        }
-    }
-
     #[test]
-    fn arc_is_cloned_downgradeable_and_upgradeable() {
-        let arc_obj = Arc::new(("hello", DetectDrop));
-        let arc_obj_clone_1 = Arc::downgrade(&arc_obj);
-        let arc_obj_clone_2 = Arc::downgrade(&arc_obj);
+    fn make_mut_only_copies_when_shared() {
+        // The address of the String inside ArcData, not its heap buffer, so it only changes if make_mut() moves to a new Arc
+        fn address(arc: &Arc<String>) -> *const String {
+            &**arc
+        }
 
-        let t = thread::spawn(move || {
-            // weak pointer is upgradeable
-            let weak_ptr = arc_obj_clone_1.upgrade().unwrap();
-            assert_eq!(weak_ptr.0, "hello");
-        });
+        let mut arc_obj = Arc::new(String::from("hello"));
+        let ptr = address(&arc_obj);
+        Arc::make_mut(&mut arc_obj).push('!');
+        // Unique, so mutated in place
+        assert_eq!(address(&arc_obj), ptr);
+        assert_eq!(*arc_obj, "hello!");
 
-        assert_eq!(arc_obj.0, "hello");
-        t.join().unwrap();
+        let arc_obj_clone_1 = arc_obj.clone();
+        Arc::make_mut(&mut arc_obj).push('?');
+        // Shared, so arc_obj has a new allocation and the clone keeps the old one
+        assert_ne!(address(&arc_obj), ptr);
+        assert_eq!(address(&arc_obj_clone_1), ptr);
+        assert_eq!(*arc_obj, "hello!?");
+        assert_eq!(*arc_obj_clone_1, "hello!");
+        assert_eq!(arc_obj.strong_count(), 1);
+        assert_eq!(arc_obj_clone_1.strong_count(), 1);
+
+        // Shared with an Arc and a Weak, the Weak stays with the clone
+        let weak_1 = Arc::downgrade(&arc_obj_clone_1);
+        let mut arc_obj_clone_2 = arc_obj_clone_1.clone();
+        Arc::make_mut(&mut arc_obj_clone_2).clear();
+        assert_ne!(address(&arc_obj_clone_2), ptr);
+        assert_eq!(address(&weak_1.upgrade().unwrap()), ptr);
+        assert_eq!(*weak_1.upgrade().unwrap(), "hello!");
+    }
 
-        // arc_obj_clone_1 has been dropped, but T still exists.
-        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
-        assert!(arc_obj_clone_2.upgrade().is_some());
+    #[test]
+    fn make_mut_disassociates_weak_pointers() {
+        let num_drops = StdArc::new(AtomicUsize::new(0));
+        let mut arc_obj = Arc::new(("hello", DetectDrop(num_drops.clone())));
+        let weak = Arc::downgrade(&arc_obj);
+        let ptr: *const _ = &*arc_obj;
+
+        Arc::make_mut(&mut arc_obj).0 = "bye";
+        // Moved to a new allocation, the Weak still holds the old one
+        assert_ne!(&*arc_obj as *const _, ptr);
+        // T was moved, not cloned (a clone would have counted a drop by now, when the old T went) or dropped
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(arc_obj.0, "bye");
+        assert_eq!(arc_obj.weak_count(), 1);
 
+        drop(weak);
         drop(arc_obj);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
 
-        // main arc has been dropped, so T is deallocated, weak pointer cannot be upgraded anymore, but ArcData.data_ref_count and alloc_ref_count still exist
-        assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 1);
-        assert!(arc_obj_clone_2.upgrade().is_none());
+    #[test]
+    fn unsized_slices_and_str() {
+        let num_drops = StdArc::new(AtomicUsize::new(0));
+        let mut arc_slice: Arc<[DetectDrop]> = Arc::from(
+            (0..5)
+                .map(|_| DetectDrop(num_drops.clone()))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(arc_slice.len(), 5);
+        // Moved into the Arc, not dropped
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+        assert_eq!(Arc::get_mut(&mut arc_slice).unwrap().len(), 5);
+
+        let weak = Arc::downgrade(&arc_slice);
+        let arc_slice_clone_1 = arc_slice.clone();
+        drop(arc_slice);
+        assert_eq!(weak.upgrade().unwrap().len(), 5);
+        drop(arc_slice_clone_1);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 5);
+        assert!(weak.upgrade().is_none());
+
+        let arc_empty: Arc<[u64]> = Arc::from(Vec::new());
+        assert!(arc_empty.is_empty());
+
+        let arc_str: Arc<str> = Arc::from(String::from("hello world!"));
+        let arc_str_clone_1 = arc_str.clone();
+        assert_eq!(&*arc_str_clone_1, "hello world!");
+        assert_eq!(arc_str.strong_count(), 2);
+    }
+
+    #[test]
+    fn unsized_trait_objects() {
+        let num_drops = StdArc::new(AtomicUsize::new(0));
+        let detect_drop = DetectDrop(num_drops.clone());
+        let add_one = move |x: u64| {
+            // Capture all of detect_drop, not just the field
+            let detect_drop = &detect_drop;
+            x + detect_drop.0.load(Ordering::Relaxed) as u64 + 1
+        };
+        let arc_fn: Arc<dyn Fn(u64) -> u64 + Send + Sync> =
+            Arc::from(Box::new(add_one) as Box<dyn Fn(u64) -> u64 + Send + Sync>);
+
+        let arc_fn_clone_1 = arc_fn.clone();
+        let t = thread::spawn(move || arc_fn_clone_1(1));
+        assert_eq!(t.join().unwrap(), 2);
+        assert_eq!(arc_fn(2), 3);
+
+        // The closure, and the DetectDrop it owns, are dropped through the vtable
+        drop(arc_fn);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+
+        // A more aligned value than the counts, behind a dyn pointer
+        #[repr(align(64))]
+        struct Aligned(u8);
+        let arc_any: Arc<dyn std::any::Any + Send + Sync> =
+            Arc::from(Box::new(Aligned(7)) as Box<dyn std::any::Any + Send + Sync>);
+        let aligned = arc_any.downcast_ref::<Aligned>().unwrap();
+        assert_eq!(aligned as *const Aligned as usize % 64, 0);
+        assert_eq!(aligned.0, 7);
     }
 
     #[test]
@@ -256,6 +538,7 @@ mod tests {
         drop(arc_obj_clone_1);
 
         assert_eq!(arc_obj.strong_count(), 1);
-        assert_eq!(arc_obj.weak_count(), 0);
+        // Still held by the remaining Arc
+        assert_eq!(arc_obj.weak_count(), 1);
     }
 }
@@ -1,6 +1,7 @@
 mod arc_basic;
 mod arc_strong_and_weak;
 mod arc_weak_pointers;
+mod ref_counted;
 
 #[allow(unused)]
 pub use arc_basic::*;
@@ -8,3 +9,5 @@ pub use arc_basic::*;
 pub use arc_strong_and_weak::*;
 #[allow(unused, ambiguous_glob_reexports)]
 pub use arc_weak_pointers::*;
+#[allow(unused)]
+pub use ref_counted::*;
@@ -0,0 +1,472 @@
+use std::ops::Deref;
+
+use super::{arc_basic, arc_strong_and_weak, arc_weak_pointers};
+
+/**
+ * RefCounted covers the three Arcs in arc_basic, arc_weak_pointers and arc_strong_and_weak. WeakRefCounted adds the Weak pointers that the last two have, and its weak_count() leaves out the counts the Arcs keep for themselves.
+ */
+pub trait RefCounted {
+    type Arc<T: Send + Sync>: Clone + Deref<Target = T> + Send + Sync;
+
+    fn new<T: Send + Sync>(data: T) -> Self::Arc<T>;
+
+    fn strong_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize;
+
+    fn get_mut<T: Send + Sync>(arc: &mut Self::Arc<T>) -> Option<&mut T>;
+
+    fn try_unwrap<T: Send + Sync>(arc: Self::Arc<T>) -> Result<T, Self::Arc<T>>;
+
+    fn into_inner<T: Send + Sync>(arc: Self::Arc<T>) -> Option<T>;
+
+    fn unwrap_or_clone<T: Send + Sync + Clone>(arc: Self::Arc<T>) -> T;
+}
+
+pub trait WeakRefCounted: RefCounted {
+    type Weak<T: Send + Sync>: Clone + Send + Sync;
+
+    fn downgrade<T: Send + Sync>(arc: &Self::Arc<T>) -> Self::Weak<T>;
+
+    fn upgrade<T: Send + Sync>(weak: &Self::Weak<T>) -> Option<Self::Arc<T>>;
+
+    fn weak_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize;
+}
+
+pub struct ArcBasic;
+pub struct ArcWeakPointers;
+pub struct ArcStrongAndWeak;
+
+impl RefCounted for ArcBasic {
+    type Arc<T: Send + Sync> = arc_basic::Arc<T>;
+
+    fn new<T: Send + Sync>(data: T) -> Self::Arc<T> {
+        arc_basic::Arc::new(data)
+    }
+
+    fn strong_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize {
+        arc.count()
+    }
+
+    fn get_mut<T: Send + Sync>(arc: &mut Self::Arc<T>) -> Option<&mut T> {
+        arc_basic::Arc::get_mut(arc)
+    }
+
+    fn try_unwrap<T: Send + Sync>(arc: Self::Arc<T>) -> Result<T, Self::Arc<T>> {
+        arc_basic::Arc::try_unwrap(arc)
+    }
+
+    fn into_inner<T: Send + Sync>(arc: Self::Arc<T>) -> Option<T> {
+        arc_basic::Arc::into_inner(arc)
+    }
+
+    fn unwrap_or_clone<T: Send + Sync + Clone>(arc: Self::Arc<T>) -> T {
+        arc_basic::Arc::unwrap_or_clone(arc)
+    }
+}
+
+impl RefCounted for ArcWeakPointers {
+    type Arc<T: Send + Sync> = arc_weak_pointers::Arc<T>;
+
+    fn new<T: Send + Sync>(data: T) -> Self::Arc<T> {
+        arc_weak_pointers::Arc::new(data)
+    }
+
+    fn strong_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize {
+        arc.strong_count()
+    }
+
+    fn get_mut<T: Send + Sync>(arc: &mut Self::Arc<T>) -> Option<&mut T> {
+        arc_weak_pointers::Arc::get_mut(arc)
+    }
+
+    fn try_unwrap<T: Send + Sync>(arc: Self::Arc<T>) -> Result<T, Self::Arc<T>> {
+        arc_weak_pointers::Arc::try_unwrap(arc)
+    }
+
+    fn into_inner<T: Send + Sync>(arc: Self::Arc<T>) -> Option<T> {
+        arc_weak_pointers::Arc::into_inner(arc)
+    }
+
+    fn unwrap_or_clone<T: Send + Sync + Clone>(arc: Self::Arc<T>) -> T {
+        arc_weak_pointers::Arc::unwrap_or_clone(arc)
+    }
+}
+
+impl WeakRefCounted for ArcWeakPointers {
+    type Weak<T: Send + Sync> = arc_weak_pointers::Weak<T>;
+
+    fn downgrade<T: Send + Sync>(arc: &Self::Arc<T>) -> Self::Weak<T> {
+        arc_weak_pointers::Arc::downgrade(arc)
+    }
+
+    fn upgrade<T: Send + Sync>(weak: &Self::Weak<T>) -> Option<Self::Arc<T>> {
+        weak.upgrade()
+    }
+
+    fn weak_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize {
+        // One Weak inside each Arc
+        arc.weak_count() - arc.strong_count()
+    }
+}
+
+impl RefCounted for ArcStrongAndWeak {
+    type Arc<T: Send + Sync> = arc_strong_and_weak::Arc<T>;
+
+    fn new<T: Send + Sync>(data: T) -> Self::Arc<T> {
+        arc_strong_and_weak::Arc::new(data)
+    }
+
+    fn strong_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize {
+        arc.strong_count()
+    }
+
+    fn get_mut<T: Send + Sync>(arc: &mut Self::Arc<T>) -> Option<&mut T> {
+        arc_strong_and_weak::Arc::get_mut(arc)
+    }
+
+    fn try_unwrap<T: Send + Sync>(arc: Self::Arc<T>) -> Result<T, Self::Arc<T>> {
+        arc_strong_and_weak::Arc::try_unwrap(arc)
+    }
+
+    fn into_inner<T: Send + Sync>(arc: Self::Arc<T>) -> Option<T> {
+        arc_strong_and_weak::Arc::into_inner(arc)
+    }
+
+    fn unwrap_or_clone<T: Send + Sync + Clone>(arc: Self::Arc<T>) -> T {
+        arc_strong_and_weak::Arc::unwrap_or_clone(arc)
+    }
+}
+
+impl WeakRefCounted for ArcStrongAndWeak {
+    type Weak<T: Send + Sync> = arc_strong_and_weak::Weak<T>;
+
+    fn downgrade<T: Send + Sync>(arc: &Self::Arc<T>) -> Self::Weak<T> {
+        arc_strong_and_weak::Arc::downgrade(arc)
+    }
+
+    fn upgrade<T: Send + Sync>(weak: &Self::Weak<T>) -> Option<Self::Arc<T>> {
+        weak.upgrade()
+    }
+
+    fn weak_count<T: Send + Sync>(arc: &Self::Arc<T>) -> usize {
+        // The one implicit Weak shared by the Arcs
+        arc.weak_count() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RefCounted, WeakRefCounted};
+    use crate::test_util::DetectDrop;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    const THREADS: usize = 8;
+    const ITERATIONS: usize = 1000;
+
+    fn drop_exactly_once<R: RefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let clone = arc.clone();
+                s.spawn(move || drop(clone));
+            }
+        });
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+
+        drop(arc);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
+
+    fn get_mut_needs_a_unique_arc<R: RefCounted>() {
+        let mut arc = R::new(String::from("hello"));
+        R::get_mut(&mut arc).unwrap().push_str(" world");
+        assert_eq!(*arc, "hello world");
+
+        let mut clone = arc.clone();
+        assert!(R::get_mut(&mut arc).is_none());
+        assert!(R::get_mut(&mut clone).is_none());
+
+        drop(clone);
+        assert!(R::get_mut(&mut arc).is_some());
+    }
+
+    fn strong_count_accounting<R: RefCounted>() {
+        let arc = R::new(1);
+        assert_eq!(R::strong_count(&arc), 1);
+
+        let clones: Vec<_> = (0..5).map(|_| arc.clone()).collect();
+        assert_eq!(R::strong_count(&arc), 6);
+
+        drop(clones);
+        assert_eq!(R::strong_count(&arc), 1);
+    }
+
+    fn clone_and_drop_storm<R: RefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let arc = &arc;
+                s.spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        let clone = arc.clone();
+                        let clone_of_clone = clone.clone();
+                        drop(clone);
+                        drop(clone_of_clone);
+                    }
+                });
+            }
+        });
+        assert_eq!(R::strong_count(&arc), 1);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+
+        drop(arc);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
+
+    fn try_unwrap_needs_a_unique_arc<R: RefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+        let clone = arc.clone();
+
+        let Err(arc) = R::try_unwrap(arc) else {
+            panic!("try_unwrap() with a clone around should fail")
+        };
+        assert_eq!(R::strong_count(&arc), 2);
+        drop(clone);
+
+        // T is moved out, not dropped
+        let detect_drop = R::try_unwrap(arc).ok().unwrap();
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+        drop(detect_drop);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
+
+    fn into_inner_gives_t_to_exactly_one_owner<R: RefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let mut taken = 0;
+
+        for _ in 0..ITERATIONS {
+            let arc = R::new(DetectDrop(num_drops.clone()));
+            let clone = arc.clone();
+            // try_unwrap() could fail on both threads here, into_inner() cannot
+            taken += thread::scope(|s| {
+                let other = s.spawn(move || R::into_inner(clone).is_some() as usize);
+                R::into_inner(arc).is_some() as usize + other.join().unwrap()
+            });
+        }
+        assert_eq!(taken, ITERATIONS);
+        assert_eq!(num_drops.load(Ordering::Relaxed), ITERATIONS);
+    }
+
+    fn unwrap_or_clone_only_clones_when_shared<R: RefCounted>() {
+        let arc = R::new(String::from("hello"));
+        let clone = arc.clone();
+
+        let cloned = R::unwrap_or_clone(clone);
+        assert_eq!(cloned, "hello");
+        assert_ne!(cloned.as_ptr(), arc.as_ptr());
+
+        let ptr = arc.as_ptr();
+        let unwrapped = R::unwrap_or_clone(arc);
+        assert_eq!(unwrapped.as_ptr(), ptr);
+    }
+
+    fn upgrade_fails_after_drop<R: WeakRefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+        let weak = R::downgrade(&arc);
+        assert!(R::upgrade(&weak).is_some());
+
+        drop(arc);
+        // The data goes with the last Arc, even though a Weak is still around
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+        assert!(R::upgrade(&weak).is_none());
+        assert!(R::upgrade(&weak.clone()).is_none());
+
+        drop(weak);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
+
+    fn get_mut_refuses_while_weak_exists<R: WeakRefCounted>() {
+        let mut arc = R::new(0);
+        let weak = R::downgrade(&arc);
+        // The Weak could be upgraded whilst the &mut is held
+        assert!(R::get_mut(&mut arc).is_none());
+
+        drop(weak);
+        *R::get_mut(&mut arc).unwrap() += 1;
+        assert_eq!(*arc, 1);
+    }
+
+    fn weak_count_accounting<R: WeakRefCounted>() {
+        let arc = R::new(1);
+        assert_eq!(R::weak_count(&arc), 0);
+
+        // Arcs are not Weaks
+        let clone = arc.clone();
+        assert_eq!(R::weak_count(&arc), 0);
+
+        let weaks: Vec<_> = (0..3).map(|_| R::downgrade(&arc)).collect();
+        let weak_clone = weaks[0].clone();
+        assert_eq!(R::weak_count(&arc), 4);
+
+        let upgraded = R::upgrade(&weak_clone).unwrap();
+        assert_eq!(R::strong_count(&arc), 3);
+        assert_eq!(R::weak_count(&arc), 4);
+
+        drop(upgraded);
+        drop(clone);
+        drop(weaks);
+        assert_eq!(R::strong_count(&arc), 1);
+        assert_eq!(R::weak_count(&arc), 1);
+
+        drop(weak_clone);
+        assert_eq!(R::weak_count(&arc), 0);
+    }
+
+    fn try_unwrap_ignores_weak_pointers<R: WeakRefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+        let weak = R::downgrade(&arc);
+
+        let detect_drop = R::try_unwrap(arc).ok().unwrap();
+        assert!(R::upgrade(&weak).is_none());
+        drop(weak);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 0);
+
+        drop(detect_drop);
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+
+        let arc = R::new(DetectDrop(num_drops.clone()));
+        let weak = R::downgrade(&arc);
+        drop(R::into_inner(arc).unwrap());
+        assert!(R::upgrade(&weak).is_none());
+        assert_eq!(num_drops.load(Ordering::Relaxed), 2);
+    }
+
+    fn downgrade_and_upgrade_storm<R: WeakRefCounted>() {
+        let num_drops = Arc::new(AtomicUsize::new(0));
+        let arc = R::new(DetectDrop(num_drops.clone()));
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let arc = &arc;
+                s.spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        let weak = R::downgrade(arc);
+                        let upgraded = R::upgrade(&weak).unwrap();
+                        drop(weak);
+                        drop(upgraded);
+                    }
+                });
+            }
+        });
+        assert_eq!(R::strong_count(&arc), 1);
+        assert_eq!(R::weak_count(&arc), 0);
+
+        // Last Arc dropped on one thread, whilst others are trying to upgrade
+        let weaks: Vec<_> = (0..THREADS).map(|_| R::downgrade(&arc)).collect();
+        thread::scope(|s| {
+            for weak in weaks {
+                s.spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        if R::upgrade(&weak).is_none() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(arc);
+        });
+        assert_eq!(num_drops.load(Ordering::Relaxed), 1);
+    }
+
+    // The same tests for every Arc
+    macro_rules! ref_counted_suite {
+        ($name:ident, $arc:ident) => {
+            mod $name {
+                #[test]
+                fn drop_exactly_once() {
+                    super::drop_exactly_once::<super::super::$arc>();
+                }
+
+                #[test]
+                fn get_mut_needs_a_unique_arc() {
+                    super::get_mut_needs_a_unique_arc::<super::super::$arc>();
+                }
+
+                #[test]
+                fn strong_count_accounting() {
+                    super::strong_count_accounting::<super::super::$arc>();
+                }
+
+                #[test]
+                fn clone_and_drop_storm() {
+                    super::clone_and_drop_storm::<super::super::$arc>();
+                }
+
+                #[test]
+                fn try_unwrap_needs_a_unique_arc() {
+                    super::try_unwrap_needs_a_unique_arc::<super::super::$arc>();
+                }
+
+                #[test]
+                fn into_inner_gives_t_to_exactly_one_owner() {
+                    super::into_inner_gives_t_to_exactly_one_owner::<super::super::$arc>();
+                }
+
+                #[test]
+                fn unwrap_or_clone_only_clones_when_shared() {
+                    super::unwrap_or_clone_only_clones_when_shared::<super::super::$arc>();
+                }
+            }
+        };
+    }
+
+    // And for the Arcs with weak pointers
+    macro_rules! weak_ref_counted_suite {
+        ($name:ident, $arc:ident) => {
+            mod $name {
+                #[test]
+                fn upgrade_fails_after_drop() {
+                    super::upgrade_fails_after_drop::<super::super::$arc>();
+                }
+
+                #[test]
+                fn get_mut_refuses_while_weak_exists() {
+                    super::get_mut_refuses_while_weak_exists::<super::super::$arc>();
+                }
+
+                #[test]
+                fn weak_count_accounting() {
+                    super::weak_count_accounting::<super::super::$arc>();
+                }
+
+                #[test]
+                fn try_unwrap_ignores_weak_pointers() {
+                    super::try_unwrap_ignores_weak_pointers::<super::super::$arc>();
+                }
+
+                #[test]
+                fn downgrade_and_upgrade_storm() {
+                    super::downgrade_and_upgrade_storm::<super::super::$arc>();
+                }
+            }
+        };
+    }
+
+    ref_counted_suite!(arc_basic, ArcBasic);
+    ref_counted_suite!(arc_weak_pointers, ArcWeakPointers);
+    ref_counted_suite!(arc_strong_and_weak, ArcStrongAndWeak);
+    weak_ref_counted_suite!(arc_weak_pointers_weak, ArcWeakPointers);
+    weak_ref_counted_suite!(arc_strong_and_weak_weak, ArcStrongAndWeak);
+}
@@ -0,0 +1,16 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+///
+/// Counts how many times it has been dropped. Each test makes its own counter, as tests run in parallel, and clones share it.
+///
+#[derive(Clone)]
+pub struct DetectDrop(pub Arc<AtomicUsize>);
+
+impl Drop for DetectDrop {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}